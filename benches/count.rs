@@ -0,0 +1,95 @@
+//! Compares counting a large file with `lwc::file` against the
+//! line-by-line loop it used before the block scanner was added, on one thread,
+//! and with the file split into chunks counted on every core.
+
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use rayon::ThreadPoolBuilder;
+
+use lwc::Options;
+
+/// At least the size from which `lwc::file` splits files into chunks when
+/// more than one thread is available.
+const SAMPLE_SIZE: usize = 64 * 1024 * 1024;
+
+fn read_lines(path: &Path) -> io::Result<[usize; 4]> {
+    let mut reader = BufReader::with_capacity(16 * 1024, fs::File::open(path)?);
+    let mut stat = [0; 4];
+    let mut buf = String::new();
+
+    loop {
+        let len = reader.read_line(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+
+        stat[0] += 1;
+        stat[1] += buf.split_whitespace().count();
+        stat[2] += buf.chars().count();
+        stat[3] += len;
+
+        buf.clear();
+    }
+
+    Ok(stat)
+}
+
+fn sample(name: &str, line: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("lwc-bench-{name}.txt"));
+
+    if fs::metadata(&path).is_ok_and(|m| m.len() as usize >= SAMPLE_SIZE) {
+        return path;
+    }
+
+    let content = line.repeat(SAMPLE_SIZE / line.len() + 1);
+    fs::write(&path, content).expect("failed to write sample file");
+
+    path
+}
+
+fn bench_count(c: &mut Criterion) {
+    let samples = [
+        (
+            "ascii",
+            "fn main() { println!(\"Hello, world!\"); } // a line of code\n",
+        ),
+        (
+            "utf8",
+            "Zażółć gęślą jaźń, 日本語のテキスト and some ASCII words\n",
+        ),
+    ];
+
+    let single = ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .expect("failed to build a single thread pool");
+
+    for (name, line) in samples {
+        let path = sample(name, line);
+        let len = fs::metadata(&path).expect("missing sample file").len();
+
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Bytes(len));
+        group.sample_size(10);
+
+        group.bench_function("read_lines", |b| {
+            b.iter(|| read_lines(black_box(&path)).unwrap())
+        });
+        group.bench_function("scan", |b| {
+            b.iter(|| single.install(|| lwc::file(black_box(&path), &Options::default()).unwrap()))
+        });
+        group.bench_function("scan_chunked", |b| {
+            b.iter(|| lwc::file(black_box(&path), &Options::default()).unwrap())
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_count);
+criterion_main!(benches);
@@ -1,18 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::io;
 use std::ops;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::thread;
+use std::time::SystemTime;
 
 use clap::{ArgAction, Parser};
 use colored::Colorize;
+use regex::Regex;
 
 use tabled::builder::Builder as TableBuilder;
 use tabled::settings::object::{Columns, Rows};
 use tabled::settings::themes::{Colorization, Theme};
-use tabled::settings::{Color, Panel, Style};
+use tabled::settings::{Alignment, Color, Panel, Style};
 
-use crate::counter::{self, DirStat, FileStat, Stat, Which};
+use lwc::{
+    BinaryMode, Count, DirStat, Encoding, EntryType, FileStat, Mark, Merge, Metric, Options,
+    Pattern, PatternMode, Stat, Which, WordMode,
+};
 
 #[derive(Debug, Parser)]
 #[command(name = "lwc", version, about, long_about = None)]
@@ -33,6 +39,65 @@ struct Args {
     #[arg(short = 't', required = false, requires = "paths")]
     pub quiet: bool,
 
+    /// Group results by file extension. In directory mode, break the number of
+    /// files down by extension.
+    #[arg(short = 'e', required = false, requires = "paths")]
+    pub group_by_ext: bool,
+
+    /// Print the count, mean, median, minimum, maximum and standard deviation
+    /// of each column over the rows of the table, below the total.
+    #[arg(long = "summary", required = false, requires = "paths")]
+    pub summary: bool,
+
+    /// Print a histogram of the input files by their number of lines, words,
+    /// chars or bytes (METRIC), in buckets of powers of two.
+    #[arg(long = "histogram", value_name = "METRIC", value_parser = parse_histogram_metric, requires = "paths", conflicts_with = "count_dir")]
+    pub histogram: Option<Count>,
+
+    /// Print the share of the total of each count and size, next to it.
+    #[arg(long = "percent", required = false, requires = "paths")]
+    pub percent: bool,
+
+    /// Print the numbers of the table with thousands separators, like
+    /// 1,549,985,410.
+    #[arg(long = "separators", required = false, requires = "paths")]
+    pub separators: bool,
+
+    /// Print the sizes in bytes of the table in SI (si, like 1.5 GB) or IEC
+    /// (iec, like 1.4 GiB) units.
+    #[arg(long = "units", value_name = "UNITS", value_parser = parse_units, requires = "paths")]
+    pub units: Option<Units>,
+
+    /// Print the numbers of the table in a compact form with k, M, G and T
+    /// suffixes, like 41.8M.
+    #[arg(long = "compact", required = false, requires = "paths")]
+    pub compact: bool,
+
+    /// Only count files of at least SIZE bytes. Accepts K, M, G and T suffixes
+    /// (powers of 1024).
+    #[arg(long = "min-size", value_name = "SIZE", value_parser = parse_size, requires = "paths", conflicts_with = "count_dir")]
+    pub min_size: Option<u64>,
+
+    /// Only count files of at most SIZE bytes. Accepts K, M, G and T suffixes
+    /// (powers of 1024).
+    #[arg(long = "max-size", value_name = "SIZE", value_parser = parse_size, requires = "paths", conflicts_with = "count_dir")]
+    pub max_size: Option<u64>,
+
+    /// Only count files modified after WHEN, either a date (2024-01-31,
+    /// 2024-01-31 12:00:00) in UTC or a duration ago (30d, 12h, 2weeks).
+    #[arg(long = "newer", value_name = "WHEN", value_parser = parse_time, requires = "paths", conflicts_with = "count_dir")]
+    pub modified_after: Option<SystemTime>,
+
+    /// Only count files modified before WHEN, either a date (2024-01-31,
+    /// 2024-01-31 12:00:00) in UTC or a duration ago (30d, 12h, 2weeks).
+    #[arg(long = "older", value_name = "WHEN", value_parser = parse_time, requires = "paths", conflicts_with = "count_dir")]
+    pub modified_before: Option<SystemTime>,
+
+    /// Only count files of the given type, like find -type: regular files (f)
+    /// or symbolic links to files (l). May be repeated.
+    #[arg(long = "type", value_name = "TYPE", value_parser = parse_type, requires = "paths", conflicts_with = "count_dir")]
+    pub types: Vec<EntryType>,
+
     /// Specify the number of threads to use.
     #[arg(short = 'T', required = false, requires = "paths")]
     pub threads: Option<usize>,
@@ -45,6 +110,64 @@ struct Args {
     #[arg(short = 'w', required = false)]
     pub print_words: bool,
 
+    /// Tell words apart by whitespace (default), by the Unicode word
+    /// boundaries (unicode) that also count each CJK ideograph as a word but
+    /// split Thai, Lao and Khmer text per character cluster rather than into
+    /// words, or count identifiers like [A-Za-z_][A-Za-z0-9_]* (identifier)
+    /// instead.
+    #[arg(long = "word-mode", value_name = "MODE", value_parser = parse_word_mode)]
+    pub word_mode: Option<WordMode>,
+
+    /// Count the matches of REGEX in each line as words.
+    #[arg(long = "word-regex", value_name = "REGEX", value_parser = parse_regex, conflicts_with_all = ["word_mode", "word_delimiters"])]
+    pub word_regex: Option<Regex>,
+
+    /// Split words on any of the characters in CHARS and on line breaks, for
+    /// example ',;' to count the fields of delimited data.
+    #[arg(
+        long = "word-delimiters",
+        value_name = "CHARS",
+        conflicts_with = "word_mode"
+    )]
+    pub word_delimiters: Option<String>,
+
+    /// Count binary files like text (count, the default), count only their
+    /// bytes (bytes), or leave them out (skip). Files starting with NUL bytes
+    /// or many control characters are binary.
+    #[arg(long = "binary", value_name = "MODE", value_parser = parse_binary_mode)]
+    pub binary: Option<BinaryMode>,
+
+    /// Decode input files as ENCODING: auto (UTF-16 when starting with a byte
+    /// order mark, UTF-8 otherwise), utf-8, utf-16le, utf-16be, latin1 or
+    /// windows-1252.
+    #[arg(long = "encoding", value_name = "ENCODING", value_parser = parse_encoding)]
+    pub encoding: Option<Encoding>,
+
+    /// Count the decompressed content of gzip, zstd, xz and bzip2 files, told
+    /// apart by their magic bytes, and print their compressed size.
+    #[arg(
+        short = 'z',
+        long = "decompress",
+        required = false,
+        conflicts_with = "count_dir"
+    )]
+    pub decompress: bool,
+
+    /// Count the members of tar (possibly compressed) and zip archives like the
+    /// files of a directory, as in release.tar.gz!/src/main.rs.
+    #[arg(long = "archives", required = false, requires = "paths")]
+    pub archives: bool,
+
+    /// Print the number of lines matching REGEX in each input file, in a
+    /// column of its own headed "REGEX (lines)". May be repeated.
+    #[arg(long = "count-pattern", value_name = "REGEX", value_parser = parse_bytes_regex, conflicts_with = "count_dir")]
+    pub count_patterns: Vec<regex::bytes::Regex>,
+
+    /// Print the number of non-empty matches of REGEX in each input file, in
+    /// a column of its own headed "REGEX (matches)". May be repeated.
+    #[arg(long = "count-matches", value_name = "REGEX", value_parser = parse_bytes_regex, conflicts_with = "count_dir")]
+    pub count_matches: Vec<regex::bytes::Regex>,
+
     /// Print the number of characters in each input file.
     #[arg(short = 'c', required = false)]
     pub print_chars: bool,
@@ -53,6 +176,37 @@ struct Args {
     #[arg(short = 'b', required = false)]
     pub print_bytes: bool,
 
+    /// Print the number of lines ending with LF, CRLF and a lone CR in each
+    /// input file, and whether it mixes them or lacks a final line ending.
+    /// Lines ending with a lone CR aren't counted in the lines column.
+    #[arg(long = "line-endings", required = false, conflicts_with = "count_dir")]
+    pub print_line_endings: bool,
+
+    /// Print the number of blank, whitespace only and trailing whitespace lines
+    /// in each input file, and of lines indented with tabs and with spaces.
+    #[arg(long = "whitespace", required = false, conflicts_with = "count_dir")]
+    pub print_whitespace: bool,
+
+    /// Print the number of grapheme clusters, the user-perceived characters, in
+    /// each input file.
+    #[arg(long = "graphemes", required = false, conflicts_with = "count_dir")]
+    pub print_graphemes: bool,
+
+    /// Print the display width in terminal columns of each input file, control
+    /// characters excluded.
+    #[arg(long = "width", required = false, conflicts_with = "count_dir")]
+    pub print_width: bool,
+
+    /// Print the length in characters and the width in terminal columns of the
+    /// longest line in each input file.
+    #[arg(long = "max-line", required = false, conflicts_with = "count_dir")]
+    pub print_max_line: bool,
+
+    /// Print the minimum, mean, median and 95th percentile line length in
+    /// characters of each input file.
+    #[arg(long = "line-stats", required = false, conflicts_with = "count_dir")]
+    pub print_line_stats: bool,
+
     /// Print the number of subdirectories in each input directory.
     #[arg(short = 's', required = false, requires = "count_dir")]
     pub print_subdirs: bool,
@@ -85,6 +239,70 @@ struct Args {
     #[arg(short = 'S', required = false, requires = "count_dir")]
     pub print_sockets: bool,
 
+    /// Print the apparent size in bytes of each input directory's entries.
+    #[arg(short = 'a', required = false, requires = "count_dir")]
+    pub print_apparent_size: bool,
+
+    /// Print the allocated size in bytes of each input directory's entries.
+    #[arg(short = 'A', required = false, requires = "count_dir")]
+    pub print_allocated_size: bool,
+
+    /// Print the apparent and allocated sizes of everything below each input
+    /// directory, like du.
+    #[arg(short = 'u', required = false, requires = "count_dir")]
+    pub print_tree_sizes: bool,
+
+    /// Print the number of symbolic links whose target does not exist in each
+    /// input directory.
+    #[arg(long = "broken-symlinks", required = false, requires = "count_dir")]
+    pub print_broken_symlinks: bool,
+
+    /// Print the number of empty regular files in each input directory.
+    #[arg(long = "empty-files", required = false, requires = "count_dir")]
+    pub print_empty_files: bool,
+
+    /// Print the number of empty subdirectories in each input directory.
+    #[arg(long = "empty-dirs", required = false, requires = "count_dir")]
+    pub print_empty_dirs: bool,
+
+    /// Print the number of executable files in each input directory.
+    #[cfg(unix)]
+    #[arg(long = "executables", required = false, requires = "count_dir")]
+    pub print_executables: bool,
+
+    /// Print the number of setuid entries in each input directory.
+    #[cfg(unix)]
+    #[arg(long = "setuid", required = false, requires = "count_dir")]
+    pub print_setuid: bool,
+
+    /// Print the number of setgid entries in each input directory.
+    #[cfg(unix)]
+    #[arg(long = "setgid", required = false, requires = "count_dir")]
+    pub print_setgid: bool,
+
+    /// Print the number of world-writable entries in each input directory.
+    #[cfg(unix)]
+    #[arg(long = "world-writable", required = false, requires = "count_dir")]
+    pub print_world_writable: bool,
+
+    /// Print the number of entries owned by a user other than the owner of
+    /// each input directory.
+    #[cfg(unix)]
+    #[arg(long = "foreign-owner", required = false, requires = "count_dir")]
+    pub print_foreign_owner: bool,
+
+    /// Print the number of entries owned by a group other than the group of
+    /// each input directory.
+    #[cfg(unix)]
+    #[arg(long = "foreign-group", required = false, requires = "count_dir")]
+    pub print_foreign_group: bool,
+
+    /// List the paths of broken symbolic links, empty files, empty
+    /// subdirectories and entries matching the permission and ownership
+    /// checks. Only the selected kinds are listed, if any.
+    #[arg(short = 'p', required = false, requires = "count_dir")]
+    pub list_marked: bool,
+
     /// Print the number of symbolic link files (a symbolic link that is also a file)
     /// in each input directory.
     #[cfg(windows)]
@@ -107,40 +325,176 @@ pub fn run() -> io::Result<()> {
 
     colored::control::set_override(args.colors);
 
+    let threads = args
+        .threads
+        .unwrap_or_else(|| match thread::available_parallelism() {
+            Ok(n) => n.get(),
+            Err(e) => {
+                eprintln!(
+                    "{}: Failed to retrieve the number of CPUs: {e}",
+                    "lwc".red()
+                );
+                1
+            }
+        });
+
+    let word_mode = match (&args.word_regex, &args.word_delimiters) {
+        (Some(regex), _) => WordMode::Regex(regex.clone()),
+        (None, Some(delimiters)) => WordMode::Delimiters(delimiters.chars().collect()),
+        (None, None) => args.word_mode.clone().unwrap_or_default(),
+    };
+
+    let mut options = Options::builder()
+        .which(if args.count_dir {
+            Which::Dir
+        } else {
+            Which::File
+        })
+        .recursive(args.recursive)
+        .threads(threads)
+        .cumulative(args.print_tree_sizes)
+        .marks(marks(&args))
+        .list_marked(args.list_marked)
+        .min_size(args.min_size)
+        .max_size(args.max_size)
+        .modified_after(args.modified_after)
+        .modified_before(args.modified_before)
+        .types(args.types.clone())
+        .line_stats(args.print_max_line || args.print_line_stats)
+        .unicode_stats(args.print_graphemes || args.print_width)
+        .line_endings(args.print_line_endings)
+        .whitespace_stats(args.print_whitespace)
+        .word_mode(word_mode)
+        .binary(args.binary.unwrap_or_default())
+        .encoding(args.encoding.unwrap_or_default())
+        .decompress(args.decompress)
+        .archives(args.archives);
+
+    for &count in &counts(&args) {
+        options = options.metric(count);
+    }
+
+    for regex in &args.count_patterns {
+        options = options.metric(Pattern::new(regex.clone(), PatternMode::Lines));
+    }
+
+    for regex in &args.count_matches {
+        options = options.metric(Pattern::new(regex.clone(), PatternMode::Matches));
+    }
+
+    let options = options.build()?;
+
     match &args.paths {
         Some(paths) => {
-            let stats = counter::count_many(
-                paths,
-                if args.count_dir {
-                    Which::Dir
-                } else {
-                    Which::File
-                },
-                args.recursive,
-                args.threads
-                    .unwrap_or_else(|| match thread::available_parallelism() {
-                        Ok(n) => n.get(),
-                        Err(e) => {
-                            eprintln!(
-                                "{}: Failed to retrieve the number of CPUs: {e}",
-                                "lwc".red()
-                            );
-                            1
-                        }
-                    }),
-            )?;
-
-            print_stats(&stats, &args);
+            let stats = lwc::count_many(paths, &options)?;
+            print_stats(&stats, &args, &options);
         }
         None => {
-            let stat = counter::stdin()?;
-            print_stdin_stats(&stat, &args);
+            let stat = lwc::stdin(&options)?;
+            print_stdin_stats(&stat, &args, &options);
         }
     }
 
     Ok(())
 }
 
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+
+    let n = digits
+        .parse::<u64>()
+        .map_err(|e| format!("invalid size `{s}`: {e}"))?;
+
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("invalid size unit `{unit}`")),
+    };
+
+    n.checked_mul(multiplier)
+        .ok_or_else(|| format!("size `{s}` is too large"))
+}
+
+fn parse_time(s: &str) -> Result<SystemTime, String> {
+    if let Ok(ago) = humantime::parse_duration(s) {
+        return SystemTime::now()
+            .checked_sub(ago)
+            .ok_or_else(|| format!("duration `{s}` is too large"));
+    }
+
+    humantime::parse_rfc3339_weak(s)
+        .or_else(|_| humantime::parse_rfc3339_weak(&format!("{s} 00:00:00")))
+        .map_err(|_| format!("invalid date or duration `{s}`"))
+}
+
+fn parse_type(s: &str) -> Result<EntryType, String> {
+    match s {
+        "f" => Ok(EntryType::File),
+        "l" => Ok(EntryType::Symlink),
+        _ => Err(format!("unknown type `{s}`")),
+    }
+}
+
+fn parse_word_mode(s: &str) -> Result<WordMode, String> {
+    match s {
+        "whitespace" => Ok(WordMode::Whitespace),
+        "unicode" => Ok(WordMode::Unicode),
+        "identifier" => Ok(WordMode::Identifier),
+        _ => Err(format!("unknown word mode `{s}`")),
+    }
+}
+
+fn parse_binary_mode(s: &str) -> Result<BinaryMode, String> {
+    match s {
+        "count" => Ok(BinaryMode::Count),
+        "bytes" => Ok(BinaryMode::Bytes),
+        "skip" => Ok(BinaryMode::Skip),
+        _ => Err(format!("unknown binary mode `{s}`")),
+    }
+}
+
+fn parse_encoding(s: &str) -> Result<Encoding, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "auto" => Ok(Encoding::Auto),
+        "utf-8" | "utf8" => Ok(Encoding::Utf8),
+        "utf-16le" | "utf16le" => Ok(Encoding::Utf16Le),
+        "utf-16be" | "utf16be" => Ok(Encoding::Utf16Be),
+        "latin1" | "iso-8859-1" => Ok(Encoding::Latin1),
+        "windows-1252" | "cp1252" => Ok(Encoding::Windows1252),
+        _ => Err(format!("unknown encoding `{s}`")),
+    }
+}
+
+fn parse_histogram_metric(s: &str) -> Result<Count, String> {
+    Count::ALL
+        .iter()
+        .find(|count| count.name() == s)
+        .copied()
+        .ok_or_else(|| format!("unknown histogram metric `{s}`"))
+}
+
+fn parse_units(s: &str) -> Result<Units, String> {
+    match s {
+        "si" => Ok(Units::Si),
+        "iec" => Ok(Units::Iec),
+        _ => Err(format!("unknown units `{s}`")),
+    }
+}
+
+fn parse_regex(s: &str) -> Result<Regex, String> {
+    Regex::new(s).map_err(|e| e.to_string())
+}
+
+fn parse_bytes_regex(s: &str) -> Result<regex::bytes::Regex, String> {
+    regex::bytes::Regex::new(s).map_err(|e| e.to_string())
+}
+
 #[derive(Debug)]
 enum Total {
     File(FileStat),
@@ -158,12 +512,7 @@ impl Total {
 
     fn update_file(&mut self, fs: &FileStat) {
         match self {
-            Self::File(s) => {
-                s.lines += fs.lines;
-                s.words += fs.words;
-                s.chars += fs.chars;
-                s.bytes += fs.bytes;
-            }
+            Self::File(s) => *s += fs.clone(),
             Self::Dir(_) => (),
         }
     }
@@ -189,6 +538,25 @@ impl Total {
                     s.symlink_files += ds.symlink_files;
                     s.symlink_dirs += ds.symlink_dirs;
                 }
+
+                s.broken_symlinks += ds.broken_symlinks;
+                s.empty_files += ds.empty_files;
+                s.empty_dirs += ds.empty_dirs;
+
+                #[cfg(unix)]
+                if cfg!(unix) {
+                    s.executables += ds.executables;
+                    s.setuid += ds.setuid;
+                    s.setgid += ds.setgid;
+                    s.world_writable += ds.world_writable;
+                    s.foreign_owner += ds.foreign_owner;
+                    s.foreign_group += ds.foreign_group;
+                }
+
+                s.apparent_size += ds.apparent_size;
+                s.allocated_size += ds.allocated_size;
+                s.tree_apparent_size += ds.tree_apparent_size;
+                s.tree_allocated_size += ds.tree_allocated_size;
             }
         }
     }
@@ -212,10 +580,16 @@ impl ops::AddAssign<&Stat> for Total {
     }
 }
 
-fn print_stats(stats: &HashMap<PathBuf, io::Result<Stat>>, args: &Args) {
+#[derive(Debug, Default)]
+struct Group {
+    files: usize,
+    stat: FileStat,
+}
+
+fn print_stats(stats: &HashMap<PathBuf, io::Result<Stat>>, args: &Args, options: &Options) {
     let mut table_builder = TableBuilder::new();
 
-    add_columns(&mut table_builder, args);
+    add_columns(&mut table_builder, args, options);
 
     let mut errors = 0;
     let mut total = if args.count_dir {
@@ -224,13 +598,37 @@ fn print_stats(stats: &HashMap<PathBuf, io::Result<Stat>>, args: &Args) {
         Total::file()
     };
 
+    let mut groups: BTreeMap<String, Group> = BTreeMap::new();
+    let mut summary = Summary::default();
+    // Rows are added once the total is known, to print their share of it.
+    let mut rows = vec![];
+
     for (path, stat) in stats {
         match stat {
             Ok(s) => {
-                if !args.quiet {
+                if args.group_by_ext {
                     match s {
-                        Stat::File(fs) => add_file_row(&mut table_builder, path, fs, args),
-                        Stat::Dir(ds) => add_dir_row(&mut table_builder, path, ds, args),
+                        Stat::File(fs) => {
+                            let group = groups.entry(lwc::extension(path)).or_default();
+                            group.files += 1;
+                            group.stat += fs.clone();
+                        }
+                        Stat::Dir(ds) => {
+                            for (ext, n) in &ds.extensions {
+                                groups.entry(ext.clone()).or_default().files += n;
+                            }
+                        }
+                    }
+                } else {
+                    let values = match s {
+                        Stat::File(fs) => file_values(fs, true, args, options),
+                        Stat::Dir(ds) => dir_values(ds, args),
+                    };
+
+                    summary.add(&values);
+
+                    if !args.quiet {
+                        rows.push((path.display().to_string(), values));
                     }
                 }
 
@@ -247,9 +645,67 @@ fn print_stats(stats: &HashMap<PathBuf, io::Result<Stat>>, args: &Args) {
         println!();
     }
 
-    match &total {
-        Total::File(fs) => add_file_row(&mut table_builder, "total", fs, args),
-        Total::Dir(ds) => add_dir_row(&mut table_builder, "total", ds, args),
+    // Subtrees of nested directories overlap, so in recursive mode only the
+    // input directories contribute to the cumulative total.
+    if let Total::Dir(ds) = &mut total
+        && args.recursive
+    {
+        ds.tree_apparent_size = 0;
+        ds.tree_allocated_size = 0;
+
+        for path in args.paths.iter().flatten() {
+            if let Some(Ok(Stat::Dir(root))) = stats.get(path) {
+                ds.tree_apparent_size += root.tree_apparent_size;
+                ds.tree_allocated_size += root.tree_allocated_size;
+            }
+        }
+    }
+
+    let total_values = if args.group_by_ext {
+        for (ext, group) in &groups {
+            let values = group_values(group, args, options);
+            summary.add(&values);
+
+            if !args.quiet {
+                let name = if ext.is_empty() {
+                    "(no extension)".to_owned()
+                } else {
+                    format!(".{ext}")
+                };
+
+                rows.push((name, values));
+            }
+        }
+
+        let group = match total {
+            Total::File(fs) => Group {
+                files: groups.values().map(|g| g.files).sum(),
+                stat: fs,
+            },
+            Total::Dir(ds) => Group {
+                files: ds.files,
+                stat: FileStat::default(),
+            },
+        };
+
+        group_values(&group, args, options)
+    } else {
+        match &total {
+            Total::File(fs) => file_values(fs, false, args, options),
+            Total::Dir(ds) => dir_values(ds, args),
+        }
+    };
+
+    let percent_of = args.percent.then_some(total_values.as_slice());
+
+    for (name, values) in rows {
+        add_row(&mut table_builder, name, &values, percent_of, args);
+    }
+
+    add_row(&mut table_builder, "total", &total_values, None, args);
+
+    if args.summary {
+        summary.add_rows(&mut table_builder, args);
     }
 
     let mut table = table_builder.build();
@@ -295,51 +751,268 @@ fn print_stats(stats: &HashMap<PathBuf, io::Result<Stat>>, args: &Args) {
     }
 
     table.with(theme);
+    table.modify(Columns::new(1..), Alignment::right());
 
     println!("{table}");
+
+    if let Some(metric) = args.histogram {
+        print_histogram(stats, metric);
+    }
+
+    if args.list_marked {
+        print_marked(stats);
+    }
 }
 
-fn print_stdin_stats(fs: &FileStat, args: &Args) {
-    let stats = [
-        ("line", fs.lines, args.print_lines),
-        ("word", fs.words, args.print_words),
-        ("char", fs.chars, args.print_chars),
-        ("byte", fs.bytes, args.print_bytes),
-    ];
+/// Width of the longest bar of a histogram.
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Prints the number of files by metric value, on a log scale: files with a
+/// value of 0, of 1, of 2 to 3, of 4 to 7, and so on.
+fn print_histogram(stats: &HashMap<PathBuf, io::Result<Stat>>, metric: Count) {
+    let mut buckets = BTreeMap::<u32, usize>::new();
+
+    for stat in stats.values() {
+        if let Ok(Stat::File(fs)) = stat {
+            let value = metric.get(fs);
+            *buckets
+                .entry(usize::BITS - value.leading_zeros())
+                .or_default() += 1;
+        }
+    }
+
+    let (Some((&first, _)), Some((&last, _))) =
+        (buckets.first_key_value(), buckets.last_key_value())
+    else {
+        return;
+    };
+
+    let files = buckets.values().sum::<usize>();
+    let largest = buckets.values().copied().max().unwrap_or_default();
+
+    let rows = (first..=last)
+        .map(|bucket| {
+            let label = match bucket {
+                0 => "0".to_owned(),
+                1 => "1".to_owned(),
+                b => format!("{}-{}", 1u128 << (b - 1), (1u128 << b) - 1),
+            };
 
-    let fmt = stats
+            (label, buckets.get(&bucket).copied().unwrap_or_default())
+        })
+        .collect::<Vec<_>>();
+
+    let label_width = rows
         .iter()
-        .filter(|(_, count, print)| {
-            if stats.iter().all(|(_, _, print)| !*print) {
-                *count >= 1
-            } else {
-                *print
-            }
+        .map(|(label, _)| label.len())
+        .chain([metric.name().len()])
+        .max()
+        .unwrap_or_default();
+    let count_width = files.to_string().len();
+
+    println!();
+    println!(
+        "{:>label_width$}  {}",
+        metric.name().green().bold(),
+        "files".green().bold()
+    );
+
+    for (label, count) in rows {
+        let bar = "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(largest));
+        let percent = count as f64 * 100.0 / files as f64;
+
+        println!(
+            "{:>label_width$}  {:<HISTOGRAM_WIDTH$}  {count:>count_width$}  {percent:>5.1}%",
+            label.cyan().bold(),
+            bar
+        );
+    }
+}
+
+/// Returns the built-in counts to print: the selected ones, or all of them when
+/// none is.
+fn counts(args: &Args) -> Vec<Count> {
+    let counts = [
+        (Count::Lines, args.print_lines),
+        (Count::Words, args.print_words),
+        (Count::Chars, args.print_chars),
+        (Count::Bytes, args.print_bytes),
+    ];
+
+    if counts.iter().all(|(_, enabled)| !*enabled) {
+        return Count::ALL.to_vec();
+    }
+
+    counts
+        .into_iter()
+        .filter_map(|(count, enabled)| enabled.then_some(count))
+        .collect()
+}
+
+/// Returns the kinds of entries to look for: the selected ones, or all of them
+/// when listing without selecting any.
+fn marks(args: &Args) -> Vec<Mark> {
+    let marks = [
+        (Mark::BrokenSymlink, args.print_broken_symlinks),
+        (Mark::EmptyFile, args.print_empty_files),
+        (Mark::EmptyDir, args.print_empty_dirs),
+        #[cfg(unix)]
+        (Mark::Executable, args.print_executables),
+        #[cfg(unix)]
+        (Mark::Setuid, args.print_setuid),
+        #[cfg(unix)]
+        (Mark::Setgid, args.print_setgid),
+        #[cfg(unix)]
+        (Mark::WorldWritable, args.print_world_writable),
+        #[cfg(unix)]
+        (Mark::ForeignOwner, args.print_foreign_owner),
+        #[cfg(unix)]
+        (Mark::ForeignGroup, args.print_foreign_group),
+    ];
+
+    let no_flags_set = marks.iter().all(|(_, enabled)| !*enabled);
+
+    if no_flags_set && args.list_marked {
+        return Mark::ALL.to_vec();
+    }
+
+    marks
+        .into_iter()
+        .filter_map(|(mark, enabled)| enabled.then_some(mark))
+        .collect()
+}
+
+fn print_marked(stats: &HashMap<PathBuf, io::Result<Stat>>) {
+    let mut marked = stats
+        .values()
+        .flatten()
+        .filter_map(|stat| match stat {
+            Stat::Dir(ds) => Some(&ds.marked),
+            Stat::File(_) => None,
         })
-        .map(|(name, count, _)| {
-            let name = if *count == 0 || *count > 1 {
-                format!("{name}s")
-            } else {
-                name.to_string()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    if marked.is_empty() {
+        return;
+    }
+
+    marked.sort_by(|(a, a_path), (b, b_path)| a.cmp(b).then(a_path.cmp(b_path)));
+
+    let width = marked
+        .iter()
+        .map(|(mark, _)| mark.to_string().len())
+        .max()
+        .unwrap_or_default();
+
+    println!();
+
+    for (mark, path) in marked {
+        println!("{:width$}  {}", mark.to_string().yellow(), path.display());
+    }
+}
+
+fn print_stdin_stats(fs: &FileStat, args: &Args, options: &Options) {
+    // Built-in counts of nothing are left out, unless selected.
+    let no_flags_set =
+        !(args.print_lines || args.print_words || args.print_chars || args.print_bytes);
+
+    let fmt = options
+        .metrics()
+        .iter()
+        .zip(&fs.metrics)
+        .filter(|(metric, value)| !no_flags_set || metric.count().is_none() || value.get() >= 1)
+        .map(|(metric, value)| {
+            let name = match metric.count() {
+                Some(_) if value.get() == 1 => metric.name().trim_end_matches('s'),
+                _ => metric.name(),
             };
 
-            let count = if *count > 0 {
-                count.to_string().green()
+            let value = if value.get() > 0 {
+                value.to_string().green()
             } else {
-                count.to_string().yellow()
+                value.to_string().yellow()
             };
 
-            format!("{count} {name}")
+            format!("{value} {name}")
         })
+        .chain(stdin_extra_stats(fs, args))
         .collect::<Vec<_>>()
         .join(" ");
 
     println!("{fmt}");
 }
 
-fn add_columns(table_builder: &mut TableBuilder, args: &Args) {
+fn stdin_extra_stats(fs: &FileStat, args: &Args) -> Vec<String> {
+    let lengths = &fs.line_lengths;
+    let mut stats = vec![];
+
+    if args.decompress {
+        stats.push(("compressed", fs.compressed_bytes.map(|n| n.to_string())));
+    }
+
+    if args.print_graphemes {
+        stats.push(("graphemes", Some(fs.graphemes.to_string())));
+    }
+
+    if args.print_width {
+        stats.push(("width", Some(fs.width.to_string())));
+    }
+
+    if args.print_line_endings {
+        let yes_no = |n: usize| if n > 0 { "yes" } else { "no" }.to_owned();
+
+        stats.push(("lf", Some(fs.lf_lines.to_string())));
+        stats.push(("crlf", Some(fs.crlf_lines.to_string())));
+        stats.push(("cr", Some(fs.cr_lines.to_string())));
+        stats.push(("mixed", Some(yes_no(fs.mixed_endings))));
+        stats.push(("no final eol", Some(yes_no(fs.missing_final_newline))));
+    }
+
+    if args.print_whitespace {
+        stats.push(("blank", Some(fs.blank_lines.to_string())));
+        stats.push(("whitespace only", Some(fs.whitespace_lines.to_string())));
+        stats.push((
+            "trailing whitespace",
+            Some(fs.trailing_whitespace_lines.to_string()),
+        ));
+        stats.push(("tab indented", Some(fs.tab_indented_lines.to_string())));
+        stats.push(("space indented", Some(fs.space_indented_lines.to_string())));
+    }
+
+    if args.print_max_line {
+        stats.push(("max line", Some(fs.max_line_chars.to_string())));
+        stats.push(("max width", Some(fs.max_line_width.to_string())));
+    }
+
+    if args.print_line_stats {
+        stats.push(("min line", lengths.min().map(|n| n.to_string())));
+        stats.push(("mean line", lengths.mean().map(|n| format!("{n:.1}"))));
+        stats.push(("median line", lengths.median().map(|n| n.to_string())));
+        stats.push(("p95 line", lengths.percentile(95.0).map(|n| n.to_string())));
+    }
+
+    stats
+        .into_iter()
+        .map(|(name, value)| match value {
+            Some(value) => format!("{} {name}", value.green()),
+            None => format!("{} {name}", "-".yellow()),
+        })
+        .collect()
+}
+
+fn add_columns(table_builder: &mut TableBuilder, args: &Args, options: &Options) {
     let mut columns = vec![String::new()];
 
+    if args.group_by_ext {
+        columns.push("files".to_owned());
+
+        if args.count_dir {
+            table_builder.push_record(columns);
+            return;
+        }
+    }
+
     if args.count_dir {
         let dir_columns = [
             ("subdirs", args.print_subdirs),
@@ -372,25 +1045,64 @@ fn add_columns(table_builder: &mut TableBuilder, args: &Args) {
                 }
             }
         }
-    } else {
-        let file_columns = [
-            ("lines", args.print_lines),
-            ("words", args.print_words),
-            ("chars", args.print_chars),
-            ("bytes", args.print_bytes),
+
+        let extra_columns = [
+            ("broken symlinks", args.print_broken_symlinks),
+            ("empty files", args.print_empty_files),
+            ("empty dirs", args.print_empty_dirs),
+            #[cfg(unix)]
+            ("executables", args.print_executables),
+            #[cfg(unix)]
+            ("setuid", args.print_setuid),
+            #[cfg(unix)]
+            ("setgid", args.print_setgid),
+            #[cfg(unix)]
+            ("world-writable", args.print_world_writable),
+            #[cfg(unix)]
+            ("foreign owner", args.print_foreign_owner),
+            #[cfg(unix)]
+            ("foreign group", args.print_foreign_group),
+            ("apparent", args.print_apparent_size),
+            ("allocated", args.print_allocated_size),
+            ("tree apparent", args.print_tree_sizes),
+            ("tree allocated", args.print_tree_sizes),
         ];
 
-        let no_flags_set = file_columns.iter().all(|(_, enabled)| !*enabled);
+        for (name, enabled) in extra_columns {
+            if enabled {
+                columns.push(name.to_owned());
+            }
+        }
+    } else {
+        for metric in options.metrics() {
+            columns.push(metric.name().to_owned());
+        }
 
-        if no_flags_set {
-            file_columns
-                .iter()
-                .for_each(|(name, _)| columns.push((*name).to_owned()));
-        } else {
-            for (name, enabled) in file_columns {
-                if enabled {
-                    columns.push(name.to_owned());
-                }
+        let extra_columns = [
+            ("compressed", args.decompress),
+            ("graphemes", args.print_graphemes),
+            ("width", args.print_width),
+            ("lf", args.print_line_endings),
+            ("crlf", args.print_line_endings),
+            ("cr", args.print_line_endings),
+            ("mixed", args.print_line_endings),
+            ("no final eol", args.print_line_endings),
+            ("blank", args.print_whitespace),
+            ("whitespace only", args.print_whitespace),
+            ("trailing whitespace", args.print_whitespace),
+            ("tab indented", args.print_whitespace),
+            ("space indented", args.print_whitespace),
+            ("max line", args.print_max_line),
+            ("max width", args.print_max_line),
+            ("min line", args.print_line_stats),
+            ("mean line", args.print_line_stats),
+            ("median line", args.print_line_stats),
+            ("p95 line", args.print_line_stats),
+        ];
+
+        for (name, enabled) in extra_columns {
+            if enabled {
+                columns.push(name.to_owned());
             }
         }
     }
@@ -398,62 +1110,279 @@ fn add_columns(table_builder: &mut TableBuilder, args: &Args) {
     table_builder.push_record(columns);
 }
 
-fn add_file_row(
-    table_builder: &mut TableBuilder,
-    path: impl AsRef<Path>,
-    fs: &FileStat,
-    args: &Args,
-) {
-    let mut row = vec![path.as_ref().display().to_string()];
+/// Value of a cell of the table.
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    /// A count, summed into the total.
+    Count(u64),
+    /// A size in bytes, summed into the total, or a statistic of sizes.
+    Bytes(f64),
+    /// A number that doesn't add up into the total, like a maximum or a mean.
+    Number(f64),
+    /// Whether a file has a property, counted as 1 into the total.
+    Flag(bool),
+    /// A missing value, like the mean line length of an empty file.
+    None,
+}
 
-    let stats = [
-        (fs.lines, args.print_lines),
-        (fs.words, args.print_words),
-        (fs.chars, args.print_chars),
-        (fs.bytes, args.print_bytes),
-    ];
+impl Value {
+    fn get(self) -> Option<f64> {
+        match self {
+            Self::Count(n) => Some(n as f64),
+            Self::Bytes(n) | Self::Number(n) => Some(n),
+            Self::Flag(b) => Some(f64::from(u8::from(b))),
+            Self::None => None,
+        }
+    }
+
+    /// Returns the share of `total` in percent of a value summed into it.
+    fn percent_of(self, total: Self) -> Option<f64> {
+        match (self, total) {
+            (Self::Count(_) | Self::Flag(_), Self::Count(total)) if total > 0 => {
+                Some(self.get().unwrap_or_default() * 100.0 / total as f64)
+            }
+            (Self::Bytes(n), Self::Bytes(total)) if total > 0.0 => Some(n * 100.0 / total),
+            _ => None,
+        }
+    }
 
-    let no_flags_set = stats.iter().all(|(_, enabled)| !*enabled);
+    /// Formats the value as set by the --separators, --units and --compact
+    /// options.
+    fn format(self, args: &Args) -> String {
+        match (self, args.units) {
+            (Self::Bytes(n), Some(units)) => units.format(n),
+            (Self::Count(_) | Self::Bytes(_) | Self::Number(_), _) if args.compact => {
+                let n = self.get().unwrap_or_default();
+                scale(n, 1000.0, &["", "k", "M", "G", "T", "P", "E"]).replace(' ', "")
+            }
+            _ if args.separators => separate(&self.to_string()),
+            _ => self.to_string(),
+        }
+    }
+}
 
-    if no_flags_set {
-        stats
-            .iter()
-            .for_each(|(value, _)| row.push(value.to_string()));
+/// Units of sizes in bytes.
+#[derive(Debug, Clone, Copy)]
+enum Units {
+    /// Powers of 1000: kB, MB, GB...
+    Si,
+    /// Powers of 1024: KiB, MiB, GiB...
+    Iec,
+}
+
+impl Units {
+    fn format(self, bytes: f64) -> String {
+        match self {
+            Self::Si => scale(bytes, 1000.0, &["B", "kB", "MB", "GB", "TB", "PB", "EB"]),
+            Self::Iec => scale(
+                bytes,
+                1024.0,
+                &["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"],
+            ),
+        }
+    }
+}
+
+/// Formats `n` in the largest of `units` it is at least one of once rounded to
+/// one decimal, each unit being `base` times the previous one, like `1.4 GiB`.
+fn scale(n: f64, base: f64, units: &[&str]) -> String {
+    let mut n = n;
+    let mut unit = 0;
+
+    while (n * 10.0).round() / 10.0 >= base && unit + 1 < units.len() {
+        n /= base;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", Value::Number(n), units[0])
+            .trim_end()
+            .to_owned()
     } else {
-        for (value, enabled) in stats {
-            if enabled {
-                row.push(value.to_string());
-            }
+        format!("{n:.1} {}", units[unit])
+    }
+}
+
+/// Inserts thousands separators in the integer part of a formatted number.
+fn separate(n: &str) -> String {
+    let (int, fract) = n.split_at(n.find('.').unwrap_or(n.len()));
+    let mut separated = String::new();
+
+    for (i, c) in int.chars().enumerate() {
+        if i > 0 && (int.len() - i).is_multiple_of(3) {
+            separated.push(',');
         }
+        separated.push(c);
     }
 
-    table_builder.push_record(row);
+    separated + fract
+}
+
+impl From<Option<f64>> for Value {
+    fn from(n: Option<f64>) -> Self {
+        n.map_or(Self::None, Self::Number)
+    }
 }
 
-fn add_dir_row(
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Count(n) => write!(f, "{n}"),
+            Self::Flag(b) => write!(f, "{}", if b { "yes" } else { "no" }),
+            Self::Bytes(n) | Self::Number(n) if n.fract() == 0.0 => write!(f, "{n}"),
+            Self::Bytes(n) | Self::Number(n) => write!(f, "{n:.1}"),
+            Self::None => write!(f, "-"),
+        }
+    }
+}
+
+/// Adds a row to the table, with the share of `percent_of` of each value summed
+/// into it if given.
+fn add_row(
     table_builder: &mut TableBuilder,
-    path: impl AsRef<Path>,
-    ds: &DirStat,
+    name: impl Into<String>,
+    values: &[Value],
+    percent_of: Option<&[Value]>,
     args: &Args,
 ) {
-    let mut row = vec![path.as_ref().display().to_string()];
+    let mut row = vec![name.into()];
+
+    row.extend(values.iter().enumerate().map(|(i, value)| {
+        let total = percent_of.and_then(|total| total.get(i));
+        let formatted = value.format(args);
+
+        match total.and_then(|total| value.percent_of(*total)) {
+            Some(percent) => format!("{formatted} ({percent:.1}%)"),
+            None => formatted,
+        }
+    }));
+
+    table_builder.push_record(row);
+}
+
+fn group_values(group: &Group, args: &Args, options: &Options) -> Vec<Value> {
+    let mut values = vec![Value::Count(group.files as u64)];
+
+    if !args.count_dir {
+        values.extend(file_values(&group.stat, false, args, options));
+    }
+
+    values
+}
+
+/// Returns the values of a file, or of several files summed up if not `single`.
+fn file_values(fs: &FileStat, single: bool, args: &Args, options: &Options) -> Vec<Value> {
+    let mut values = options
+        .metrics()
+        .iter()
+        .enumerate()
+        .map(|(i, metric)| metric_value(metric.as_ref(), fs, i, single))
+        .collect::<Vec<_>>();
+
+    if args.decompress {
+        values.push(
+            fs.compressed_bytes
+                .map_or(Value::None, |n| Value::Bytes(n as f64)),
+        );
+    }
+
+    if args.print_graphemes {
+        values.push(Value::Count(fs.graphemes as u64));
+    }
+
+    if args.print_width {
+        values.push(Value::Count(fs.width as u64));
+    }
+
+    if args.print_line_endings {
+        values.push(Value::Count(fs.lf_lines as u64));
+        values.push(Value::Count(fs.crlf_lines as u64));
+        values.push(Value::Count(fs.cr_lines as u64));
+
+        for n in [fs.mixed_endings, fs.missing_final_newline] {
+            values.push(if single {
+                Value::Flag(n > 0)
+            } else {
+                Value::Count(n as u64)
+            });
+        }
+    }
+
+    if args.print_whitespace {
+        values.push(Value::Count(fs.blank_lines as u64));
+        values.push(Value::Count(fs.whitespace_lines as u64));
+        values.push(Value::Count(fs.trailing_whitespace_lines as u64));
+        values.push(Value::Count(fs.tab_indented_lines as u64));
+        values.push(Value::Count(fs.space_indented_lines as u64));
+    }
+
+    if args.print_max_line {
+        values.push(Value::Number(fs.max_line_chars as f64));
+        values.push(Value::Number(fs.max_line_width as f64));
+    }
+
+    if args.print_line_stats {
+        let lengths = &fs.line_lengths;
+
+        values.push(Value::from(lengths.min().map(|n| n as f64)));
+        values.push(Value::from(lengths.mean()));
+        values.push(Value::from(lengths.median().map(|n| n as f64)));
+        values.push(Value::from(lengths.percentile(95.0).map(|n| n as f64)));
+    }
+
+    values
+}
+
+/// Returns the value of the `i`th metric of a file, or of several files summed
+/// up if not `single`.
+fn metric_value(metric: &dyn Metric, fs: &FileStat, i: usize, single: bool) -> Value {
+    let value = fs.metrics.get(i);
+
+    match metric.count() {
+        Some(count) => {
+            // Totals of no file have no metrics, but built-in counts of 0.
+            let n = value.map_or(count.get(fs), |value| value.get());
+
+            match count {
+                Count::Bytes => Value::Bytes(n as f64),
+                _ => Value::Count(n as u64),
+            }
+        }
+        // Binary files aren't fed to custom metrics.
+        None if single && fs.binary => Value::None,
+        None => match value {
+            Some(value) if matches!(value.merge(), Merge::Sum) => Value::Count(value.get() as u64),
+            Some(value) => Value::Number(value.get() as f64),
+            None => Value::None,
+        },
+    }
+}
+
+fn dir_values(ds: &DirStat, args: &Args) -> Vec<Value> {
+    let mut values = vec![];
 
     let dir_metrics = [
-        (ds.subdirs, args.print_subdirs),
-        (ds.files, args.print_files),
-        (ds.symlinks, args.print_symlinks),
+        (Value::Count(ds.subdirs as u64), args.print_subdirs),
+        (Value::Count(ds.files as u64), args.print_files),
+        (Value::Count(ds.symlinks as u64), args.print_symlinks),
         #[cfg(unix)]
-        (ds.blocks, args.print_blocks),
+        (Value::Count(ds.blocks as u64), args.print_blocks),
         #[cfg(unix)]
-        (ds.chars, args.print_chards),
+        (Value::Count(ds.chars as u64), args.print_chards),
         #[cfg(unix)]
-        (ds.fifos, args.print_fifos),
+        (Value::Count(ds.fifos as u64), args.print_fifos),
         #[cfg(unix)]
-        (ds.sockets, args.print_sockets),
+        (Value::Count(ds.sockets as u64), args.print_sockets),
         #[cfg(windows)]
-        (ds.symlink_files, args.print_symlink_files),
+        (
+            Value::Count(ds.symlink_files as u64),
+            args.print_symlink_files,
+        ),
         #[cfg(windows)]
-        (ds.symlink_dirs, args.print_symlink_dirs),
+        (
+            Value::Count(ds.symlink_dirs as u64),
+            args.print_symlink_dirs,
+        ),
     ];
 
     let no_flags_set = dir_metrics.iter().all(|(_, enabled)| !*enabled);
@@ -461,14 +1390,234 @@ fn add_dir_row(
     if no_flags_set {
         dir_metrics
             .iter()
-            .for_each(|(value, _)| row.push(value.to_string()));
+            .for_each(|(value, _)| values.push(*value));
     } else {
         for (value, enabled) in dir_metrics {
             if enabled {
-                row.push(value.to_string());
+                values.push(value);
             }
         }
     }
 
-    table_builder.push_record(row);
+    let extra_metrics = [
+        (
+            Value::Count(ds.broken_symlinks as u64),
+            args.print_broken_symlinks,
+        ),
+        (Value::Count(ds.empty_files as u64), args.print_empty_files),
+        (Value::Count(ds.empty_dirs as u64), args.print_empty_dirs),
+        #[cfg(unix)]
+        (Value::Count(ds.executables as u64), args.print_executables),
+        #[cfg(unix)]
+        (Value::Count(ds.setuid as u64), args.print_setuid),
+        #[cfg(unix)]
+        (Value::Count(ds.setgid as u64), args.print_setgid),
+        #[cfg(unix)]
+        (
+            Value::Count(ds.world_writable as u64),
+            args.print_world_writable,
+        ),
+        #[cfg(unix)]
+        (
+            Value::Count(ds.foreign_owner as u64),
+            args.print_foreign_owner,
+        ),
+        #[cfg(unix)]
+        (
+            Value::Count(ds.foreign_group as u64),
+            args.print_foreign_group,
+        ),
+        (
+            Value::Bytes(ds.apparent_size as f64),
+            args.print_apparent_size,
+        ),
+        (
+            Value::Bytes(ds.allocated_size as f64),
+            args.print_allocated_size,
+        ),
+        (
+            Value::Bytes(ds.tree_apparent_size as f64),
+            args.print_tree_sizes,
+        ),
+        (
+            Value::Bytes(ds.tree_allocated_size as f64),
+            args.print_tree_sizes,
+        ),
+    ];
+
+    for (value, enabled) in extra_metrics {
+        if enabled {
+            values.push(value);
+        }
+    }
+
+    values
+}
+
+/// Values of the rows of the table by column, summarized in rows below the
+/// total. Missing values are left out.
+#[derive(Debug, Default)]
+struct Summary {
+    columns: Vec<Vec<f64>>,
+    /// Whether each column holds sizes in bytes.
+    bytes: Vec<bool>,
+}
+
+impl Summary {
+    fn add(&mut self, values: &[Value]) {
+        if self.columns.len() < values.len() {
+            self.columns.resize(values.len(), vec![]);
+            self.bytes.resize(values.len(), false);
+        }
+
+        for (i, value) in values.iter().enumerate() {
+            if let Some(n) = value.get() {
+                self.columns[i].push(n);
+            }
+
+            self.bytes[i] |= matches!(value, Value::Bytes(_));
+        }
+    }
+
+    fn add_rows(mut self, table_builder: &mut TableBuilder, args: &Args) {
+        let stats = self
+            .columns
+            .iter_mut()
+            .map(|column| summarize(column))
+            .collect::<Vec<_>>();
+
+        for (i, name) in ["count", "mean", "median", "min", "max", "stddev"]
+            .into_iter()
+            .enumerate()
+        {
+            let values = stats
+                .iter()
+                .zip(&self.bytes)
+                .map(|(stat, &bytes)| match stat[i] {
+                    Some(n) if bytes && name != "count" => Value::Bytes(n),
+                    n => Value::from(n),
+                })
+                .collect::<Vec<_>>();
+
+            add_row(table_builder, name, &values, None, args);
+        }
+    }
+}
+
+/// Returns the count, mean, median, minimum, maximum and standard deviation of
+/// the values of a column, sorting them.
+fn summarize(column: &mut [f64]) -> [Option<f64>; 6] {
+    let n = column.len();
+
+    if n == 0 {
+        return [Some(0.0), None, None, None, None, None];
+    }
+
+    column.sort_by(f64::total_cmp);
+
+    let mean = column.iter().sum::<f64>() / n as f64;
+    let median = if n.is_multiple_of(2) {
+        (column[n / 2 - 1] + column[n / 2]) / 2.0
+    } else {
+        column[n / 2]
+    };
+    let variance = column.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n as f64;
+
+    [
+        Some(n as f64),
+        Some(mean),
+        Some(median),
+        Some(column[0]),
+        Some(column[n - 1]),
+        Some(variance.sqrt()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn summarize_columns() {
+        let [count, mean, median, min, max, stddev] = summarize(&mut [4.0, 1.0, 3.0, 2.0]);
+
+        assert_eq!(count, Some(4.0));
+        assert_eq!(mean, Some(2.5));
+        assert_eq!(median, Some(2.5));
+        assert_eq!((min, max), (Some(1.0), Some(4.0)));
+        assert_eq!(stddev, Some(1.25f64.sqrt()));
+
+        assert_eq!(summarize(&mut [3.0, 1.0, 2.0])[2], Some(2.0));
+        assert_eq!(
+            summarize(&mut []),
+            [Some(0.0), None, None, None, None, None]
+        );
+    }
+
+    #[test]
+    fn scaled_numbers() {
+        let si = ["B", "kB", "MB", "GB", "TB", "PB", "EB"];
+        let iec = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+        assert_eq!(scale(999.0, 1000.0, &si), "999 B");
+        assert_eq!(scale(1500.0, 1000.0, &si), "1.5 kB");
+        assert_eq!(scale(999_960.0, 1000.0, &si), "1.0 MB");
+        assert_eq!(scale(999.96, 1000.0, &si), "1.0 kB");
+        assert_eq!(scale(1_048_575.0, 1024.0, &iec), "1.0 MiB");
+        assert_eq!(scale(1_500_000_000.0, 1024.0, &iec), "1.4 GiB");
+        assert_eq!(scale(41_800_000.0, 1000.0, &["", "k", "M"]), "41.8 M");
+        assert_eq!(scale(2e21, 1000.0, &si), "2000.0 EB");
+    }
+
+    #[test]
+    fn separated_numbers() {
+        assert_eq!(separate("1549985410"), "1,549,985,410");
+        assert_eq!(separate("123456"), "123,456");
+        assert_eq!(separate("999"), "999");
+        assert_eq!(separate("1234.5"), "1,234.5");
+        assert_eq!(separate("-"), "-");
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("10K"), Ok(10 * 1024));
+        assert_eq!(parse_size("3mib"), Ok(3 * 1024 * 1024));
+        assert_eq!(parse_size("2GB"), Ok(2 << 30));
+        assert!(parse_size("1X").is_err());
+        assert!(parse_size("K").is_err());
+        assert!(parse_size("16777216T").is_err());
+    }
+
+    #[test]
+    fn types() {
+        assert_eq!(parse_type("f"), Ok(EntryType::File));
+        assert_eq!(parse_type("l"), Ok(EntryType::Symlink));
+
+        for s in ["d", "b", "c", "p", "s", "x"] {
+            assert!(parse_type(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn times() {
+        let day = UNIX_EPOCH + Duration::from_secs(1_706_659_200);
+
+        assert_eq!(parse_time("2024-01-31"), Ok(day));
+        assert_eq!(
+            parse_time("2024-01-31 12:00:00"),
+            Ok(day + Duration::from_secs(12 * 3600))
+        );
+
+        // The system clock isn't monotonic, hence the margin.
+        let ago = SystemTime::now()
+            .duration_since(parse_time("30d").expect("time"))
+            .unwrap_or_default();
+        let month = Duration::from_secs(30 * 86400);
+        assert!(ago.abs_diff(month) < Duration::from_secs(60), "{ago:?}");
+
+        assert!(parse_time("soon").is_err());
+    }
 }
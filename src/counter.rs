@@ -1,45 +1,328 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::num::NonZeroUsize;
 use std::ops;
 use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[cfg(unix)]
-use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
 
 #[cfg(windows)]
 use std::os::windows::fs::FileTypeExt;
 
-use rayon::ThreadPoolBuilder;
+use encoding_rs::DecoderResult;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::{ThreadPoolBuilder, current_num_threads};
+use regex::Regex;
+use serde::Serialize;
+use serde::ser::{SerializeStruct, Serializer};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 use walkdir::WalkDir;
 
-#[derive(Debug, Default)]
+use crate::metric::{Count, LineMatcher, Metric, MetricCounter, MetricValue, PatternMode};
+
+/// Files at least this large are split into chunks of [`CHUNK_SIZE`] bytes,
+/// counted in parallel when more than one thread is available.
+const CHUNK_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+const CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Number of bytes after a chunk boundary searched for a character no word
+/// spans, with the word modes that need one to split chunks.
+const CHUNK_SEARCH: u64 = 64 * 1024;
+
+/// Size of the read buffer used by [`scan`]. Counting never holds more than one
+/// buffer of a file in memory, however long its lines are.
+const SCAN_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Text handed to [`Scanner::count_text`] is split into blocks of at most this
+/// many bytes, small enough to stay in the L1 cache across the counting passes.
+const SCAN_BLOCK_SIZE: usize = 4 * 1024;
+
+/// Maximum length of the text held back by a [`Carry`] to find where its last
+/// segment ends. A longer grapheme cluster or token is counted once and
+/// flushed.
+const MAX_CARRY: usize = 4 * 1024;
+
+/// Number of bytes at the start of a file looked at to tell whether it's
+/// binary.
+const BINARY_SNIFF_SIZE: usize = 8 * 1024;
+
+/// Counts of a file or of files added together. The optional groups of
+/// statistics are left out of its serialization unless they were computed.
+#[derive(Debug, Default, Clone)]
+#[non_exhaustive]
 pub struct FileStat {
     pub lines: usize,
     pub words: usize,
     pub chars: usize,
     pub bytes: usize,
+    /// Length in characters of the longest line. Only computed when
+    /// [`OptionsBuilder::line_stats`] is set, like the other line statistics.
+    pub max_line_chars: usize,
+    /// Width in terminal columns of the widest line, with tabs expanded to the
+    /// next multiple of 8 like `wc -L`.
+    pub max_line_width: usize,
+    pub line_lengths: LineLengths,
+    /// Number of extended grapheme clusters. Only computed when
+    /// [`OptionsBuilder::unicode_stats`] is set, like the display width.
+    pub graphemes: usize,
+    /// Width in terminal columns, control characters excluded.
+    pub width: usize,
+    /// Number of lines ending with a line feed alone. Only computed when
+    /// [`OptionsBuilder::line_endings`] is set, like the other line ending counts.
+    pub lf_lines: usize,
+    pub crlf_lines: usize,
+    /// Number of lines ending with a carriage return alone, as in classic Mac
+    /// OS text. These aren't counted in [`FileStat::lines`], so the line ending
+    /// counts add up to `lines` plus `cr_lines`, minus an unterminated last
+    /// line.
+    pub cr_lines: usize,
+    /// Number of files with more than one kind of line ending, so 1 or 0 for a
+    /// single file.
+    pub mixed_endings: usize,
+    /// Number of non-empty files whose last line has no line ending.
+    pub missing_final_newline: usize,
+    /// Number of empty lines. Only computed when [`OptionsBuilder::whitespace_stats`]
+    /// is set, like the other whitespace counts.
+    pub blank_lines: usize,
+    /// Number of non-empty lines made of whitespace only.
+    pub whitespace_lines: usize,
+    /// Number of lines ending with whitespace, whitespace only lines included.
+    pub trailing_whitespace_lines: usize,
+    /// Number of lines starting with a tab, whitespace only lines excluded.
+    pub tab_indented_lines: usize,
+    /// Number of lines starting with a space, whitespace only lines excluded.
+    pub space_indented_lines: usize,
+    /// Whether the file was found to be binary, in which case only its bytes
+    /// are counted. Never set when [`OptionsBuilder::binary`] is [`BinaryMode::Count`].
+    pub binary: bool,
+    /// Number of compressed bytes read, for a file decompressed to be counted,
+    /// `None` otherwise. Its bytes are then the decompressed bytes.
+    pub compressed_bytes: Option<usize>,
+    /// Values of the [`Options::metrics`], in the same order. The custom metrics
+    /// of binary files aren't fed their content, and have the value of an empty
+    /// input.
+    pub metrics: Vec<MetricValue>,
+    computed: Computed,
+}
+
+/// Optional groups of statistics computed for a [`FileStat`], or for any of
+/// the files added together into it.
+#[derive(Debug, Default, Clone, Copy)]
+struct Computed {
+    line_stats: bool,
+    unicode_stats: bool,
+    line_endings: bool,
+    whitespace_stats: bool,
 }
 
 impl FileStat {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Returns the counts of a binary file, of which only the bytes are
+    /// counted.
+    fn binary(bytes: usize, options: &Options) -> Self {
+        let mut stat = Self {
+            bytes,
+            binary: true,
+            ..Self::default()
+        };
+
+        stat.metrics = options
+            .metrics
+            .iter()
+            .map(|m| Counter::new(m.as_ref()).finish(&stat))
+            .collect();
+
+        stat
+    }
 }
 
 impl ops::AddAssign for FileStat {
     fn add_assign(&mut self, rhs: Self) {
+        let mut line_lengths = std::mem::take(&mut self.line_lengths);
+        line_lengths += &rhs.line_lengths;
+
+        let mut metrics = std::mem::take(&mut self.metrics);
+        for (i, value) in rhs.metrics.into_iter().enumerate() {
+            match metrics.get_mut(i) {
+                Some(total) => *total += value,
+                None => metrics.push(value),
+            }
+        }
+
         *self = Self {
             lines: self.lines + rhs.lines,
             words: self.words + rhs.words,
             chars: self.chars + rhs.chars,
             bytes: self.bytes + rhs.bytes,
+            max_line_chars: self.max_line_chars.max(rhs.max_line_chars),
+            max_line_width: self.max_line_width.max(rhs.max_line_width),
+            line_lengths,
+            graphemes: self.graphemes + rhs.graphemes,
+            width: self.width + rhs.width,
+            lf_lines: self.lf_lines + rhs.lf_lines,
+            crlf_lines: self.crlf_lines + rhs.crlf_lines,
+            cr_lines: self.cr_lines + rhs.cr_lines,
+            mixed_endings: self.mixed_endings + rhs.mixed_endings,
+            missing_final_newline: self.missing_final_newline + rhs.missing_final_newline,
+            blank_lines: self.blank_lines + rhs.blank_lines,
+            whitespace_lines: self.whitespace_lines + rhs.whitespace_lines,
+            trailing_whitespace_lines: self.trailing_whitespace_lines
+                + rhs.trailing_whitespace_lines,
+            tab_indented_lines: self.tab_indented_lines + rhs.tab_indented_lines,
+            space_indented_lines: self.space_indented_lines + rhs.space_indented_lines,
+            binary: self.binary || rhs.binary,
+            compressed_bytes: self
+                .compressed_bytes
+                .into_iter()
+                .chain(rhs.compressed_bytes)
+                .reduce(|a, b| a + b),
+            metrics,
+            computed: Computed {
+                line_stats: self.computed.line_stats || rhs.computed.line_stats,
+                unicode_stats: self.computed.unicode_stats || rhs.computed.unicode_stats,
+                line_endings: self.computed.line_endings || rhs.computed.line_endings,
+                whitespace_stats: self.computed.whitespace_stats || rhs.computed.whitespace_stats,
+            },
         }
     }
 }
 
-#[derive(Debug, Default)]
+impl Serialize for FileStat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Computed {
+            line_stats,
+            unicode_stats,
+            line_endings,
+            whitespace_stats,
+        } = self.computed;
+
+        let mut s = serializer.serialize_struct("FileStat", 22)?;
+        s.serialize_field("lines", &self.lines)?;
+        s.serialize_field("words", &self.words)?;
+        s.serialize_field("chars", &self.chars)?;
+        s.serialize_field("bytes", &self.bytes)?;
+        serialize_if(&mut s, line_stats, "max_line_chars", &self.max_line_chars)?;
+        serialize_if(&mut s, line_stats, "max_line_width", &self.max_line_width)?;
+        serialize_if(&mut s, line_stats, "line_lengths", &self.line_lengths)?;
+        serialize_if(&mut s, unicode_stats, "graphemes", &self.graphemes)?;
+        serialize_if(&mut s, unicode_stats, "width", &self.width)?;
+        serialize_if(&mut s, line_endings, "lf_lines", &self.lf_lines)?;
+        serialize_if(&mut s, line_endings, "crlf_lines", &self.crlf_lines)?;
+        serialize_if(&mut s, line_endings, "cr_lines", &self.cr_lines)?;
+        serialize_if(&mut s, line_endings, "mixed_endings", &self.mixed_endings)?;
+        serialize_if(
+            &mut s,
+            line_endings,
+            "missing_final_newline",
+            &self.missing_final_newline,
+        )?;
+        serialize_if(&mut s, whitespace_stats, "blank_lines", &self.blank_lines)?;
+        serialize_if(
+            &mut s,
+            whitespace_stats,
+            "whitespace_lines",
+            &self.whitespace_lines,
+        )?;
+        serialize_if(
+            &mut s,
+            whitespace_stats,
+            "trailing_whitespace_lines",
+            &self.trailing_whitespace_lines,
+        )?;
+        serialize_if(
+            &mut s,
+            whitespace_stats,
+            "tab_indented_lines",
+            &self.tab_indented_lines,
+        )?;
+        serialize_if(
+            &mut s,
+            whitespace_stats,
+            "space_indented_lines",
+            &self.space_indented_lines,
+        )?;
+        s.serialize_field("binary", &self.binary)?;
+        s.serialize_field("compressed_bytes", &self.compressed_bytes)?;
+        s.serialize_field("metrics", &self.metrics)?;
+        s.end()
+    }
+}
+
+/// Serializes the field if its statistic was computed, skips it otherwise.
+fn serialize_if<S: SerializeStruct, T: Serialize>(
+    s: &mut S,
+    computed: bool,
+    key: &'static str,
+    value: &T,
+) -> Result<(), S::Error> {
+    if computed {
+        s.serialize_field(key, value)
+    } else {
+        s.skip_field(key)
+    }
+}
+
+/// Number of lines of each length in characters, line terminators excluded.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct LineLengths(BTreeMap<usize, usize>);
+
+impl LineLengths {
+    pub fn count(&self) -> usize {
+        self.0.values().sum()
+    }
+
+    pub fn min(&self) -> Option<usize> {
+        self.0.keys().next().copied()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        let count = self.count();
+        let sum = self.0.iter().map(|(len, n)| len * n).sum::<usize>();
+
+        (count > 0).then(|| sum as f64 / count as f64)
+    }
+
+    pub fn median(&self) -> Option<usize> {
+        self.percentile(50.0)
+    }
+
+    /// Returns the nearest-rank `p`th percentile, `p` being in `0.0..=100.0`.
+    pub fn percentile(&self, p: f64) -> Option<usize> {
+        let rank = ((p / 100.0 * self.count() as f64).ceil() as usize).max(1);
+        let mut seen = 0;
+
+        self.0.iter().find_map(|(&len, &n)| {
+            seen += n;
+            (seen >= rank).then_some(len)
+        })
+    }
+
+    fn push(&mut self, len: usize) {
+        *self.0.entry(len).or_default() += 1;
+    }
+}
+
+impl ops::AddAssign<&LineLengths> for LineLengths {
+    fn add_assign(&mut self, rhs: &LineLengths) {
+        for (&len, &n) in &rhs.0 {
+            *self.0.entry(len).or_default() += n;
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+#[non_exhaustive]
 pub struct DirStat {
     pub subdirs: usize,
     pub files: usize,
@@ -56,16 +339,95 @@ pub struct DirStat {
     pub symlink_files: usize,
     #[cfg(windows)]
     pub symlink_dirs: usize,
+    pub broken_symlinks: usize,
+    pub empty_files: usize,
+    pub empty_dirs: usize,
+    #[cfg(unix)]
+    pub executables: usize,
+    #[cfg(unix)]
+    pub setuid: usize,
+    #[cfg(unix)]
+    pub setgid: usize,
+    #[cfg(unix)]
+    pub world_writable: usize,
+    /// Entries owned by a user other than the directory owner.
+    #[cfg(unix)]
+    pub foreign_owner: usize,
+    /// Entries owned by a group other than the directory group.
+    #[cfg(unix)]
+    pub foreign_group: usize,
+    /// Entries matching a [`Mark`]. Only collected when [`OptionsBuilder::list_marked`]
+    /// is set.
+    pub marked: Vec<(Mark, PathBuf)>,
+    /// Apparent size in bytes of the directory's direct entries.
+    pub apparent_size: u64,
+    /// Allocated size in bytes of the directory's direct entries.
+    pub allocated_size: u64,
+    /// Apparent size in bytes of everything below the directory. Only computed
+    /// when [`OptionsBuilder::cumulative`] is set.
+    pub tree_apparent_size: u64,
+    /// Allocated size in bytes of everything below the directory. Only computed
+    /// when [`OptionsBuilder::cumulative`] is set.
+    pub tree_allocated_size: u64,
+    /// Number of regular files per extension. Files without an extension are
+    /// counted under an empty key.
+    pub extensions: BTreeMap<String, usize>,
+    /// Sizes of the hard links below the directory, by device and inode, so
+    /// that [`count_many`] counts them once in the cumulative sizes.
+    #[cfg(unix)]
+    #[serde(skip)]
+    hard_links: HashMap<(u64, u64), (u64, u64)>,
 }
 
 impl DirStat {
     pub fn new() -> Self {
         DirStat::default()
     }
+
+    fn mark(&mut self, mark: Mark, path: &Path, options: &Options) {
+        if !options.marks.contains(&mark) {
+            return;
+        }
+
+        match mark {
+            Mark::BrokenSymlink => self.broken_symlinks += 1,
+            Mark::EmptyFile => self.empty_files += 1,
+            Mark::EmptyDir => self.empty_dirs += 1,
+            #[cfg(unix)]
+            Mark::Executable => self.executables += 1,
+            #[cfg(unix)]
+            Mark::Setuid => self.setuid += 1,
+            #[cfg(unix)]
+            Mark::Setgid => self.setgid += 1,
+            #[cfg(unix)]
+            Mark::WorldWritable => self.world_writable += 1,
+            #[cfg(unix)]
+            Mark::ForeignOwner => self.foreign_owner += 1,
+            #[cfg(unix)]
+            Mark::ForeignGroup => self.foreign_group += 1,
+        }
+
+        if options.list_marked {
+            self.marked.push((mark, path.to_path_buf()));
+        }
+    }
 }
 
 impl ops::AddAssign for DirStat {
     fn add_assign(&mut self, rhs: Self) {
+        let mut extensions = std::mem::take(&mut self.extensions);
+        for (ext, n) in rhs.extensions {
+            *extensions.entry(ext).or_default() += n;
+        }
+
+        let mut marked = std::mem::take(&mut self.marked);
+        marked.extend(rhs.marked);
+
+        #[cfg(unix)]
+        let mut hard_links = std::mem::take(&mut self.hard_links);
+        #[cfg(unix)]
+        hard_links.extend(rhs.hard_links);
+
         *self = Self {
             subdirs: self.subdirs + rhs.subdirs,
             files: self.files + rhs.files,
@@ -82,11 +444,100 @@ impl ops::AddAssign for DirStat {
             symlink_files: self.symlink_files + rhs.symlink_files,
             #[cfg(windows)]
             symlink_dirs: self.symlink_dirs + rhs.symlink_dirs,
+            broken_symlinks: self.broken_symlinks + rhs.broken_symlinks,
+            empty_files: self.empty_files + rhs.empty_files,
+            empty_dirs: self.empty_dirs + rhs.empty_dirs,
+            #[cfg(unix)]
+            executables: self.executables + rhs.executables,
+            #[cfg(unix)]
+            setuid: self.setuid + rhs.setuid,
+            #[cfg(unix)]
+            setgid: self.setgid + rhs.setgid,
+            #[cfg(unix)]
+            world_writable: self.world_writable + rhs.world_writable,
+            #[cfg(unix)]
+            foreign_owner: self.foreign_owner + rhs.foreign_owner,
+            #[cfg(unix)]
+            foreign_group: self.foreign_group + rhs.foreign_group,
+            marked,
+            apparent_size: self.apparent_size + rhs.apparent_size,
+            allocated_size: self.allocated_size + rhs.allocated_size,
+            tree_apparent_size: self.tree_apparent_size + rhs.tree_apparent_size,
+            tree_allocated_size: self.tree_allocated_size + rhs.tree_allocated_size,
+            extensions,
+            #[cfg(unix)]
+            hard_links,
         }
     }
 }
 
-#[derive(Debug)]
+/// Kinds of directory entries worth pointing out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Mark {
+    BrokenSymlink,
+    EmptyFile,
+    EmptyDir,
+    #[cfg(unix)]
+    Executable,
+    #[cfg(unix)]
+    Setuid,
+    #[cfg(unix)]
+    Setgid,
+    #[cfg(unix)]
+    WorldWritable,
+    #[cfg(unix)]
+    ForeignOwner,
+    #[cfg(unix)]
+    ForeignGroup,
+}
+
+impl Mark {
+    /// Every kind of mark, in display order.
+    pub const ALL: &[Mark] = &[
+        Self::BrokenSymlink,
+        Self::EmptyFile,
+        Self::EmptyDir,
+        #[cfg(unix)]
+        Self::Executable,
+        #[cfg(unix)]
+        Self::Setuid,
+        #[cfg(unix)]
+        Self::Setgid,
+        #[cfg(unix)]
+        Self::WorldWritable,
+        #[cfg(unix)]
+        Self::ForeignOwner,
+        #[cfg(unix)]
+        Self::ForeignGroup,
+    ];
+}
+
+impl fmt::Display for Mark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BrokenSymlink => write!(f, "broken symlink"),
+            Self::EmptyFile => write!(f, "empty file"),
+            Self::EmptyDir => write!(f, "empty dir"),
+            #[cfg(unix)]
+            Self::Executable => write!(f, "executable"),
+            #[cfg(unix)]
+            Self::Setuid => write!(f, "setuid"),
+            #[cfg(unix)]
+            Self::Setgid => write!(f, "setgid"),
+            #[cfg(unix)]
+            Self::WorldWritable => write!(f, "world-writable"),
+            #[cfg(unix)]
+            Self::ForeignOwner => write!(f, "foreign owner"),
+            #[cfg(unix)]
+            Self::ForeignGroup => write!(f, "foreign group"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum Stat {
     File(FileStat),
     Dir(DirStat),
@@ -104,139 +555,2614 @@ impl From<DirStat> for Stat {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
 pub enum Which {
+    #[default]
+    File,
+    Dir,
+}
+
+/// Entry types as understood by `find -type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EntryType {
     File,
     Dir,
+    Symlink,
+    #[cfg(unix)]
+    Block,
+    #[cfg(unix)]
+    Char,
+    #[cfg(unix)]
+    Fifo,
+    #[cfg(unix)]
+    Socket,
+}
+
+impl EntryType {
+    fn of(ft: fs::FileType) -> Option<Self> {
+        match ft {
+            ft if ft.is_dir() => Some(Self::Dir),
+            ft if ft.is_file() => Some(Self::File),
+            ft if ft.is_symlink() => Some(Self::Symlink),
+            #[cfg(unix)]
+            ft if ft.is_block_device() => Some(Self::Block),
+            #[cfg(unix)]
+            ft if ft.is_char_device() => Some(Self::Char),
+            #[cfg(unix)]
+            ft if ft.is_fifo() => Some(Self::Fifo),
+            #[cfg(unix)]
+            ft if ft.is_socket() => Some(Self::Socket),
+            _ => None,
+        }
+    }
+}
+
+/// Predicates a file has to satisfy to be counted. Sizes and modification
+/// times are taken from the target of symbolic links, types are not.
+/// Directories are never filtered.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Filter {
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub modified_after: Option<SystemTime>,
+    pub modified_before: Option<SystemTime>,
+    /// Accepted entry types. Empty accepts every type. Only files are counted,
+    /// so only [`EntryType::File`] and [`EntryType::Symlink`] can match.
+    pub types: Vec<EntryType>,
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.min_size.is_none()
+            && self.max_size.is_none()
+            && self.modified_after.is_none()
+            && self.modified_before.is_none()
+            && self.types.is_empty()
+    }
+
+    /// Returns whether `path` satisfies every predicate. Entries whose metadata
+    /// can't be read are let through, so the error surfaces when counting them.
+    pub fn matches(&self, path: impl AsRef<Path>) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        if !self.types.is_empty() {
+            let Ok(metadata) = path.as_ref().symlink_metadata() else {
+                return true;
+            };
+
+            match EntryType::of(metadata.file_type()) {
+                Some(t) if self.types.contains(&t) => {}
+                _ => return false,
+            }
+        }
+
+        let Ok(metadata) = path.as_ref().metadata() else {
+            return true;
+        };
+
+        self.accepts(None, metadata.len(), metadata.modified().ok())
+    }
+
+    /// Returns whether an entry of type `kind`, `size` bytes long and last
+    /// modified at `modified` satisfies every predicate. The type isn't checked
+    /// if unknown, nor the modification time.
+    fn accepts(&self, kind: Option<EntryType>, size: u64, modified: Option<SystemTime>) -> bool {
+        if kind.is_some_and(|kind| !self.types.is_empty() && !self.types.contains(&kind)) {
+            return false;
+        }
+
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+
+        let Some(modified) = modified else {
+            return true;
+        };
+
+        !(self.modified_after.is_some_and(|t| modified < t)
+            || self.modified_before.is_some_and(|t| modified > t))
+    }
+}
+
+/// How words are told apart.
+#[derive(Debug, Default, Clone)]
+#[non_exhaustive]
+pub enum WordMode {
+    /// Runs of non-whitespace characters, like `wc`.
+    #[default]
+    Whitespace,
+    /// Word boundaries of Unicode Standard Annex #29, counting the segments
+    /// with a letter or a digit, so that each ideograph of CJK text is a word.
+    /// Thai, Lao and Khmer text, written without spaces, is split per
+    /// character cluster rather than into words, as that needs a dictionary.
+    Unicode,
+    /// Identifiers of most programming languages, `[A-Za-z_][A-Za-z0-9_]*`.
+    Identifier,
+    /// Runs of characters other than the given delimiters and line breaks.
+    Delimiters(Vec<char>),
+    /// Non-empty matches of a regular expression. Matches don't span lines,
+    /// nor pieces of lines longer than 64 KiB.
+    Regex(Regex),
+}
+
+/// What to do with binary files, told apart by NUL bytes or a high ratio of
+/// control characters at their start.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BinaryMode {
+    /// Count binary files like text files, without detecting them.
+    #[default]
+    Count,
+    /// Only count the bytes of binary files.
+    Bytes,
+    /// Leave binary files out of the results of [`count_many`]. Binary input
+    /// given to other functions has only its bytes counted.
+    Skip,
+}
+
+/// Compression formats of the files that can be decompressed to be counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    /// Tells the compression format of a file from its magic bytes.
+    fn detect(head: &[u8]) -> Option<Self> {
+        match head {
+            [0x1f, 0x8b, ..] => Some(Self::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Self::Zstd),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(Self::Xz),
+            [b'B', b'Z', b'h', b'1'..=b'9', ..] => Some(Self::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// Wraps `reader` into a decoder of all the concatenated streams it holds,
+    /// as left by log rotation appending to compressed files.
+    fn decoder<'a>(self, reader: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Self::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            Self::Zstd => Box::new(zstd::Decoder::new(reader)?),
+            Self::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)),
+            Self::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        })
+    }
+}
+
+/// Text encoding of the files counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Encoding {
+    /// UTF-16 if a file starts with a UTF-16 byte order mark, UTF-8 otherwise.
+    #[default]
+    Auto,
+    Utf8,
+    /// UTF-16 with a leading byte order mark dropped, like for big endian.
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1, each byte being the code point of a character.
+    Latin1,
+    Windows1252,
+}
+
+impl Encoding {
+    /// Resolves [`Encoding::Auto`] from `head`, the start of a file.
+    fn detect(self, head: &[u8]) -> Self {
+        match (self, head) {
+            (Self::Auto, [0xff, 0xfe, ..]) => Self::Utf16Le,
+            (Self::Auto, [0xfe, 0xff, ..]) => Self::Utf16Be,
+            (Self::Auto, _) => Self::Utf8,
+            (encoding, _) => encoding,
+        }
+    }
+}
+
+/// Options of the counting functions, built with [`Options::builder`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Options {
+    pub(crate) which: Which,
+    pub(crate) recursive: bool,
+    pub(crate) threads: NonZeroUsize,
+    /// Compute the cumulative size of each directory's subtree.
+    pub(crate) cumulative: bool,
+    /// Kinds of entries to look for in directories. Others are not counted,
+    /// sparing the checks they need.
+    pub(crate) marks: Vec<Mark>,
+    /// Collect the paths of marked entries into [`DirStat::marked`].
+    pub(crate) list_marked: bool,
+    /// Files not matching the filter are skipped before being opened. Ignored
+    /// with [`Which::Dir`].
+    pub(crate) filter: Filter,
+    /// Compute the line length statistics of [`FileStat`].
+    pub(crate) line_stats: bool,
+    /// Compute the grapheme cluster count and display width of [`FileStat`].
+    pub(crate) unicode_stats: bool,
+    /// Compute the line ending counts of [`FileStat`].
+    pub(crate) line_endings: bool,
+    /// Compute the blank, whitespace and indentation line counts of
+    /// [`FileStat`].
+    pub(crate) whitespace_stats: bool,
+    pub(crate) word_mode: WordMode,
+    pub(crate) binary: BinaryMode,
+    pub(crate) encoding: Encoding,
+    /// Count the decompressed content of gzip, zstd, xz and bzip2 files.
+    pub(crate) decompress: bool,
+    /// Count the members of tar and zip archives like the files of a
+    /// directory, in [`count_many`].
+    pub(crate) archives: bool,
+    /// Custom metrics of files, counted along the built-in counts.
+    pub(crate) metrics: Vec<Arc<dyn Metric>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            which: Which::default(),
+            recursive: false,
+            threads: NonZeroUsize::MIN,
+            cumulative: false,
+            marks: Mark::ALL.to_vec(),
+            list_marked: false,
+            filter: Filter::default(),
+            line_stats: false,
+            unicode_stats: false,
+            line_endings: false,
+            whitespace_stats: false,
+            word_mode: WordMode::default(),
+            binary: BinaryMode::default(),
+            encoding: Encoding::default(),
+            decompress: false,
+            archives: false,
+            metrics: vec![],
+        }
+    }
+}
+
+impl Options {
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
+
+    /// Custom metrics of files, in the order of [`FileStat::metrics`].
+    pub fn metrics(&self) -> &[Arc<dyn Metric>] {
+        &self.metrics
+    }
+
+    /// Returns the format of `path` if it's an archive to count the members
+    /// of, which only a regular file (`is_file`) can be.
+    fn archive(&self, path: &Path, is_file: bool) -> Option<Archive> {
+        (self.archives && is_file)
+            .then(|| Archive::of(path))
+            .flatten()
+    }
+
+    /// Whether `path` passes the filter, which only applies to files. The
+    /// members of an `archive` are filtered instead.
+    fn filtered(&self, path: &Path, archive: Option<Archive>) -> bool {
+        match self.which {
+            Which::File if archive.is_some() => true,
+            Which::File => self.filter.matches(path),
+            Which::Dir => true,
+        }
+    }
+}
+
+/// Builder of [`Options`], starting from the defaults: files counted on one
+/// thread, without recursion, filters nor optional metrics.
+#[derive(Debug, Clone, Default)]
+pub struct OptionsBuilder {
+    options: Options,
+    /// Number of threads, checked by [`OptionsBuilder::build`].
+    threads: Option<usize>,
+}
+
+impl OptionsBuilder {
+    pub fn which(mut self, which: Which) -> Self {
+        self.options.which = which;
+        self
+    }
+
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.options.recursive = recursive;
+        self
+    }
+
+    /// Sets the number of threads of [`count_many`], which must not be 0.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    pub fn cumulative(mut self, cumulative: bool) -> Self {
+        self.options.cumulative = cumulative;
+        self
+    }
+
+    /// Only looks for entries of the kinds in `marks`, instead of all of them.
+    pub fn marks(mut self, marks: Vec<Mark>) -> Self {
+        self.options.marks = marks;
+        self
+    }
+
+    pub fn list_marked(mut self, list_marked: bool) -> Self {
+        self.options.list_marked = list_marked;
+        self
+    }
+
+    pub fn min_size(mut self, size: Option<u64>) -> Self {
+        self.options.filter.min_size = size;
+        self
+    }
+
+    pub fn max_size(mut self, size: Option<u64>) -> Self {
+        self.options.filter.max_size = size;
+        self
+    }
+
+    pub fn modified_after(mut self, time: Option<SystemTime>) -> Self {
+        self.options.filter.modified_after = time;
+        self
+    }
+
+    pub fn modified_before(mut self, time: Option<SystemTime>) -> Self {
+        self.options.filter.modified_before = time;
+        self
+    }
+
+    /// Only counts files of one of `types`, or of any type if empty. See
+    /// [`Filter::types`].
+    pub fn types(mut self, types: Vec<EntryType>) -> Self {
+        self.options.filter.types = types;
+        self
+    }
+
+    pub fn line_stats(mut self, line_stats: bool) -> Self {
+        self.options.line_stats = line_stats;
+        self
+    }
+
+    pub fn unicode_stats(mut self, unicode_stats: bool) -> Self {
+        self.options.unicode_stats = unicode_stats;
+        self
+    }
+
+    pub fn line_endings(mut self, line_endings: bool) -> Self {
+        self.options.line_endings = line_endings;
+        self
+    }
+
+    pub fn whitespace_stats(mut self, whitespace_stats: bool) -> Self {
+        self.options.whitespace_stats = whitespace_stats;
+        self
+    }
+
+    pub fn word_mode(mut self, word_mode: WordMode) -> Self {
+        self.options.word_mode = word_mode;
+        self
+    }
+
+    pub fn binary(mut self, binary: BinaryMode) -> Self {
+        self.options.binary = binary;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.options.encoding = encoding;
+        self
+    }
+
+    pub fn decompress(mut self, decompress: bool) -> Self {
+        self.options.decompress = decompress;
+        self
+    }
+
+    pub fn archives(mut self, archives: bool) -> Self {
+        self.options.archives = archives;
+        self
+    }
+
+    /// Adds a custom metric, whose values are appended to
+    /// [`FileStat::metrics`].
+    pub fn metric(mut self, metric: impl Metric + 'static) -> Self {
+        self.options.metrics.push(Arc::new(metric));
+        self
+    }
+
+    /// Returns the options.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::ErrorKind::InvalidInput`] error if the number of
+    /// threads is 0.
+    pub fn build(mut self) -> io::Result<Options> {
+        if let Some(threads) = self.threads {
+            self.options.threads = NonZeroUsize::new(threads).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the number of threads must not be 0",
+                )
+            })?;
+        }
+
+        Ok(self.options)
+    }
 }
 
 pub fn count_many(
     paths: &[impl AsRef<Path>],
-    which: Which,
-    recursive: bool,
-    threads: usize,
+    options: &Options,
 ) -> io::Result<HashMap<PathBuf, io::Result<Stat>>> {
-    assert_ne!(threads, 0);
-
     let workers = ThreadPoolBuilder::new()
-        .num_threads(threads)
+        .num_threads(options.threads.get())
         .build()
         .map_err(|e| io::Error::other(format!("Failed to build workers pool: {e}")))?;
 
     let mut entries = vec![];
+    // Directories walked whose cumulative sizes are summed up from their
+    // subdirectories', rather than by walking each of them again.
+    let mut walked = HashSet::new();
+    let cumulative = options.recursive && options.cumulative && matches!(options.which, Which::Dir);
 
     for path in paths {
-        if recursive {
+        if options.recursive {
             for entry in WalkDir::new(path) {
                 let entry = entry?;
                 let p = entry.path();
 
-                match which {
+                let archive = options.archive(p, entry.file_type().is_file());
+
+                if cumulative && entry.file_type().is_dir() {
+                    walked.insert(p.to_path_buf());
+                }
+
+                match options.which {
                     Which::File if p.is_dir() => continue,
-                    Which::Dir if p.is_file() => continue,
+                    Which::Dir if !p.is_dir() && archive.is_none() => continue,
                     _ => {}
                 }
 
-                entries.push(p.to_path_buf());
+                if options.filtered(p, archive) {
+                    entries.push((p.to_path_buf(), archive));
+                }
             }
         } else {
-            entries.push(path.as_ref().to_path_buf());
+            let path = path.as_ref();
+            let is_file = path.metadata().is_ok_and(|m| m.is_file());
+            let archive = options.archive(path, is_file);
+
+            if options.filtered(path, archive) {
+                entries.push((path.to_path_buf(), archive));
+            }
         }
     }
 
     let stats = workers.install(|| {
         entries
             .into_par_iter()
-            .map(|path| {
-                let stat = count(&path, which);
-                Ok((path, stat))
+            .flat_map_iter(|(path, archive)| match archive {
+                Some(archive) => count_archive(&path, archive, options),
+                _ if walked.contains(&path) => {
+                    let stat = dir_entries(&path, options).map(Stat::from);
+                    vec![(path, stat)]
+                }
+                _ => {
+                    let stat = count(&path, options);
+                    vec![(path, stat)]
+                }
             })
-            .collect::<io::Result<HashMap<_, _>>>()
-    })?;
+            .filter(|(_, stat)| {
+                options.binary != BinaryMode::Skip
+                    || !matches!(stat, Ok(Stat::File(fs)) if fs.binary)
+            })
+            .collect::<HashMap<_, _>>()
+    });
+
+    Ok(cumulate(stats, walked))
+}
+
+/// Adds the cumulative sizes of the `walked` directories to those of their
+/// parents, deepest first, so that each holds the sizes of its whole subtree.
+/// Hard links seen in more than one directory of a subtree are counted once.
+fn cumulate(
+    mut stats: HashMap<PathBuf, io::Result<Stat>>,
+    walked: HashSet<PathBuf>,
+) -> HashMap<PathBuf, io::Result<Stat>> {
+    let mut dirs = walked.into_iter().collect::<Vec<_>>();
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+    for dir in &dirs {
+        let Some(Ok(Stat::Dir(stat))) = stats.get_mut(dir) else {
+            continue;
+        };
+
+        let sizes = (stat.tree_apparent_size, stat.tree_allocated_size);
+        #[cfg(unix)]
+        let hard_links = std::mem::take(&mut stat.hard_links);
+
+        let Some(Ok(Stat::Dir(parent))) = dir.parent().and_then(|parent| stats.get_mut(parent))
+        else {
+            continue;
+        };
+
+        parent.tree_apparent_size += sizes.0;
+        parent.tree_allocated_size += sizes.1;
+
+        #[cfg(unix)]
+        for (id, (apparent, allocated)) in hard_links {
+            if parent
+                .hard_links
+                .insert(id, (apparent, allocated))
+                .is_some()
+            {
+                parent.tree_apparent_size -= apparent;
+                parent.tree_allocated_size -= allocated;
+            }
+        }
+    }
 
-    Ok(stats)
+    stats
 }
 
-pub fn count(path: impl AsRef<Path>, which: Which) -> io::Result<Stat> {
-    match which {
-        Which::File => file(path).map(Stat::from),
-        Which::Dir => dir(path).map(Stat::from),
+pub fn count(path: impl AsRef<Path>, options: &Options) -> io::Result<Stat> {
+    match options.which {
+        Which::File => file(path, options).map(Stat::from),
+        Which::Dir => dir(path, options).map(Stat::from),
     }
 }
 
-pub fn file(path: impl AsRef<Path>) -> io::Result<FileStat> {
-    if !path.as_ref().metadata()?.is_file() {
+pub fn file(path: impl AsRef<Path>, options: &Options) -> io::Result<FileStat> {
+    let metadata = path.as_ref().metadata()?;
+
+    if !metadata.is_file() {
         return Err(io::Error::new(
             io::ErrorKind::InvalidInput,
             format!("{} is not a regular file", path.as_ref().display()),
         ));
     }
 
-    let f = fs::File::open(&path)?;
-    let reader = BufReader::with_capacity(16 * 1024, f);
+    let mut f = fs::File::open(&path)?;
+    let head = read_head(&mut f)?;
 
-    read_lines(reader)
-}
+    if let Some(compression) = options
+        .decompress
+        .then(|| Compression::detect(&head))
+        .flatten()
+    {
+        return decompress(head.as_slice().chain(f), compression, options);
+    }
 
-pub fn dir(path: impl AsRef<Path>) -> io::Result<DirStat> {
-    if !path.as_ref().metadata()?.is_dir() {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            format!("{} is not a directory", path.as_ref().display()),
-        ));
+    let (encoding, binary) = sniff(&head, options);
+
+    if binary {
+        return Ok(FileStat::binary(metadata.len() as usize, options));
     }
 
-    let entries = fs::read_dir(&path)?;
-    let mut stat = DirStat::new();
+    // Lines, line endings, grapheme clusters, Unicode words, regex matches and
+    // most custom metrics may span chunks, so they need a single pass. Chunks
+    // are split as UTF-8, and between words with the identifier and delimiter
+    // modes.
+    if metadata.len() >= CHUNK_THRESHOLD
+        && encoding == Encoding::Utf8
+        && current_num_threads() > 1
+        && !options.line_stats
+        && !options.line_endings
+        && !options.whitespace_stats
+        && !options.unicode_stats
+        && options
+            .metrics
+            .iter()
+            .all(|m| m.count().is_some() || m.chunked())
+        && matches!(
+            options.word_mode,
+            WordMode::Whitespace | WordMode::Identifier | WordMode::Delimiters(_)
+        )
+    {
+        return scan_chunked(path.as_ref(), metadata.len(), CHUNK_SIZE, options);
+    }
 
-    for entry in entries.flatten() {
-        let metadata = entry.path().symlink_metadata()?;
+    // Small files don't need a full sized buffer. One more byte than the file
+    // size lets the end of the file be reached in a single read.
+    let buf_len = (metadata.len() as usize).saturating_add(1);
 
-        match metadata.file_type() {
-            ft if ft.is_dir() => stat.subdirs += 1,
-            ft if ft.is_file() => stat.files += 1,
-            ft if ft.is_symlink() => stat.symlinks += 1,
-            #[cfg(unix)]
-            ft if ft.is_block_device() => stat.blocks += 1,
-            #[cfg(unix)]
-            ft if ft.is_char_device() => stat.chars += 1,
-            #[cfg(unix)]
-            ft if ft.is_fifo() => stat.fifos += 1,
-            #[cfg(unix)]
-            ft if ft.is_socket() => stat.sockets += 1,
-            #[cfg(windows)]
-            ft if ft.is_symlink_file() => stat.symlink_files += 1,
+    scan(
+        head.as_slice().chain(f),
+        buf_len.min(SCAN_BUFFER_SIZE),
+        encoding,
+        options,
+    )
+}
+
+pub fn dir(path: impl AsRef<Path>, options: &Options) -> io::Result<DirStat> {
+    let mut stat = dir_entries(path.as_ref(), options)?;
+
+    if options.cumulative {
+        (stat.tree_apparent_size, stat.tree_allocated_size) = tree_size(&path);
+    }
+
+    Ok(stat)
+}
+
+/// Counts the direct entries of a directory. With [`OptionsBuilder::cumulative`], its
+/// cumulative sizes are those of its direct entries, hard links counted once,
+/// for [`cumulate`] to add those of its subdirectories.
+fn dir_entries(path: &Path, options: &Options) -> io::Result<DirStat> {
+    let dir_metadata = path.metadata()?;
+
+    if !dir_metadata.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a directory", path.display()),
+        ));
+    }
+
+    let entries = fs::read_dir(path)?;
+    let mut stat = DirStat::new();
+
+    for entry in entries.flatten() {
+        let entry_path = entry.path();
+        let metadata = entry_path.symlink_metadata()?;
+
+        stat.apparent_size += metadata.len();
+        stat.allocated_size += allocated_size(&metadata);
+
+        if options.cumulative {
+            let sizes = (metadata.len(), allocated_size(&metadata));
+
+            #[cfg(unix)]
+            let duplicate = !metadata.is_dir()
+                && metadata.nlink() > 1
+                && stat
+                    .hard_links
+                    .insert((metadata.dev(), metadata.ino()), sizes)
+                    .is_some();
+            #[cfg(not(unix))]
+            let duplicate = false;
+
+            if !duplicate {
+                stat.tree_apparent_size += sizes.0;
+                stat.tree_allocated_size += sizes.1;
+            }
+        }
+
+        let mark = match metadata.file_type() {
+            ft if ft.is_dir() => {
+                stat.subdirs += 1;
+
+                let empty = options.marks.contains(&Mark::EmptyDir)
+                    && fs::read_dir(&entry_path).is_ok_and(|mut d| d.next().is_none());
+                empty.then_some(Mark::EmptyDir)
+            }
+            ft if ft.is_file() => {
+                stat.files += 1;
+                *stat.extensions.entry(extension(&entry_path)).or_default() += 1;
+
+                (metadata.len() == 0).then_some(Mark::EmptyFile)
+            }
+            ft if ft.is_symlink() => {
+                stat.symlinks += 1;
+
+                let broken = options.marks.contains(&Mark::BrokenSymlink)
+                    && fs::metadata(&entry_path).is_err();
+                broken.then_some(Mark::BrokenSymlink)
+            }
+            #[cfg(unix)]
+            ft if ft.is_block_device() => {
+                stat.blocks += 1;
+                None
+            }
+            #[cfg(unix)]
+            ft if ft.is_char_device() => {
+                stat.chars += 1;
+                None
+            }
+            #[cfg(unix)]
+            ft if ft.is_fifo() => {
+                stat.fifos += 1;
+                None
+            }
+            #[cfg(unix)]
+            ft if ft.is_socket() => {
+                stat.sockets += 1;
+                None
+            }
+            #[cfg(windows)]
+            ft if ft.is_symlink_file() => {
+                stat.symlink_files += 1;
+                None
+            }
             #[cfg(windows)]
-            ft if ft.is_symlink_dir() => stat.symlink_dirs += 1,
-            _ => {}
+            ft if ft.is_symlink_dir() => {
+                stat.symlink_dirs += 1;
+                None
+            }
+            _ => None,
+        };
+
+        if let Some(mark) = mark {
+            stat.mark(mark, &entry_path, options);
+        }
+
+        #[cfg(unix)]
+        for mark in permission_marks(&metadata, &dir_metadata) {
+            stat.mark(mark, &entry_path, options);
         }
     }
 
     Ok(stat)
 }
 
-pub fn stdin() -> io::Result<FileStat> {
-    let reader = BufReader::new(io::stdin());
-    read_lines(reader)
+/// Returns the permission and ownership marks of an entry, relative to the
+/// metadata of the directory containing it.
+#[cfg(unix)]
+fn permission_marks(metadata: &fs::Metadata, dir: &fs::Metadata) -> impl Iterator<Item = Mark> {
+    let mode = metadata.mode();
+    let is_symlink = metadata.file_type().is_symlink();
+
+    [
+        (Mark::Executable, metadata.is_file() && mode & 0o111 != 0),
+        (Mark::Setuid, mode & 0o4000 != 0),
+        (Mark::Setgid, mode & 0o2000 != 0),
+        (Mark::WorldWritable, !is_symlink && mode & 0o002 != 0),
+        (Mark::ForeignOwner, metadata.uid() != dir.uid()),
+        (Mark::ForeignGroup, metadata.gid() != dir.gid()),
+    ]
+    .into_iter()
+    .filter_map(|(mark, matches)| matches.then_some(mark))
+}
+
+/// Sums the apparent and allocated sizes of everything below `path`, without
+/// following symbolic links. Hard links are only counted once on Unix.
+fn tree_size(path: impl AsRef<Path>) -> (u64, u64) {
+    let mut apparent = 0;
+    let mut allocated = 0;
+    #[cfg(unix)]
+    let mut seen = HashSet::new();
+
+    // Entries that can't be read are left out, like `du` does, rather than
+    // failing the whole directory.
+    for entry in WalkDir::new(path).min_depth(1).into_iter().flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        #[cfg(unix)]
+        if metadata.nlink() > 1 && !seen.insert((metadata.dev(), metadata.ino())) {
+            continue;
+        }
+
+        apparent += metadata.len();
+        allocated += allocated_size(&metadata);
+    }
+
+    (apparent, allocated)
+}
+
+#[cfg(unix)]
+fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+/// Archive formats whose members can be counted like the files of a directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Archive {
+    /// Tar archives, possibly compressed in any of the [`Compression`] formats.
+    Tar,
+    Zip,
 }
 
-fn read_lines(mut reader: impl BufRead) -> io::Result<FileStat> {
-    let mut stat = FileStat::new();
-    let mut buf = String::new();
+impl Archive {
+    /// Tells the archive format of a file from its name.
+    fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        let tar = [
+            ".tar", ".tgz", ".tar.gz", ".tzst", ".tar.zst", ".txz", ".tar.xz", ".tbz2", ".tar.bz2",
+        ];
 
-    loop {
-        let len = reader.read_line(&mut buf)?;
-        if len == 0 {
-            break;
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if tar.iter().any(|ext| name.ends_with(ext)) {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Entry of an archive.
+#[derive(Debug)]
+struct Member {
+    /// Path of the member in the archive, without leading `./` nor trailing
+    /// slash.
+    name: String,
+    kind: EntryType,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Calls `f` with each member of an archive and a reader of its content. Hard
+/// links of tar archives are left out, their content being another member's.
+fn members(
+    path: &Path,
+    archive: Archive,
+    mut f: impl FnMut(Member, &mut dyn Read),
+) -> io::Result<()> {
+    let mut file = fs::File::open(path)?;
+
+    let member = |name: &str, kind, size, modified| Member {
+        name: name
+            .trim_start_matches("./")
+            .trim_end_matches('/')
+            .to_owned(),
+        kind,
+        size,
+        modified,
+    };
+
+    match archive {
+        Archive::Tar => {
+            let head = read_head(&mut file)?;
+            let reader = head.as_slice().chain(file);
+            let reader: Box<dyn Read> = match Compression::detect(&head) {
+                Some(compression) => compression.decoder(reader)?,
+                None => Box::new(reader),
+            };
+
+            for entry in tar::Archive::new(reader).entries()? {
+                let mut entry = entry?;
+                let kind = match entry.header().entry_type() {
+                    t if t.is_file() || t.is_gnu_sparse() => EntryType::File,
+                    t if t.is_dir() => EntryType::Dir,
+                    t if t.is_symlink() => EntryType::Symlink,
+                    #[cfg(unix)]
+                    t if t.is_block_special() => EntryType::Block,
+                    #[cfg(unix)]
+                    t if t.is_character_special() => EntryType::Char,
+                    #[cfg(unix)]
+                    t if t.is_fifo() => EntryType::Fifo,
+                    _ => continue,
+                };
+
+                let modified = entry.header().mtime().ok();
+                let modified = modified.map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+
+                let member = member(
+                    &entry.path()?.to_string_lossy(),
+                    kind,
+                    entry.size(),
+                    modified,
+                );
+                f(member, &mut entry);
+            }
+        }
+        Archive::Zip => {
+            let mut archive = zip::ZipArchive::new(file)?;
+
+            for i in 0..archive.len() {
+                let mut entry = archive.by_index(i)?;
+                let kind = match &entry {
+                    e if e.is_dir() => EntryType::Dir,
+                    e if e.is_symlink() => EntryType::Symlink,
+                    _ => EntryType::File,
+                };
+
+                // Zip times have no time zone, they are taken as UTC.
+                let modified = entry
+                    .last_modified()
+                    .and_then(|t| humantime::parse_rfc3339_weak(&t.to_string()).ok());
+
+                let member = member(entry.name(), kind, entry.size(), modified);
+                f(member, &mut entry);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the path of an archive member, like `release.tar.gz!/src/main.rs`.
+fn member_path(archive: &Path, name: &str) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push("!/");
+    path.push(name);
+
+    PathBuf::from(path)
+}
+
+/// Counts the members of the archive at `path`, as files or as the
+/// directories holding them depending on [`OptionsBuilder::which`]. The archive itself
+/// is its root directory.
+fn count_archive(
+    path: &Path,
+    archive: Archive,
+    options: &Options,
+) -> Vec<(PathBuf, io::Result<Stat>)> {
+    let mut stats = vec![];
+
+    let result = match options.which {
+        Which::File => members(path, archive, |member, reader| {
+            let kind = Some(member.kind);
+
+            if member.kind == EntryType::File
+                && options.filter.accepts(kind, member.size, member.modified)
+            {
+                let stat = read(reader, options).map(Stat::from);
+                stats.push((member_path(path, &member.name), stat));
+            }
+        }),
+        Which::Dir => archive_dirs(path, archive, options).map(|dirs| {
+            let dirs = dirs
+                .into_iter()
+                .filter(|(name, _)| options.recursive || name.is_empty())
+                .map(|(name, stat)| match name.as_str() {
+                    "" => (path.to_path_buf(), Ok(Stat::from(stat))),
+                    name => (member_path(path, name), Ok(Stat::from(stat))),
+                });
+
+            stats.extend(dirs);
+        }),
+    };
+
+    if let Err(e) = result {
+        stats.push((path.to_path_buf(), Err(e)));
+    }
+
+    stats
+}
+
+/// Returns the parent of a path in an archive, the root being "".
+fn member_parent(name: &str) -> &str {
+    name.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+/// Returns the counts of each directory of an archive, by path in the archive.
+/// Directories missing from the archive but holding members are included.
+fn archive_dirs(
+    path: &Path,
+    archive: Archive,
+    options: &Options,
+) -> io::Result<BTreeMap<String, DirStat>> {
+    // Directories with their number of entries.
+    let mut dirs = BTreeMap::from([(String::new(), (DirStat::new(), 0))]);
+
+    fn add_dir(dirs: &mut BTreeMap<String, (DirStat, usize)>, name: &str) {
+        if dirs.contains_key(name) {
+            return;
+        }
+
+        let parent = member_parent(name);
+        add_dir(dirs, parent);
+
+        let (stat, entries) = dirs.entry(parent.to_owned()).or_default();
+        stat.subdirs += 1;
+        *entries += 1;
+
+        dirs.insert(name.to_owned(), (DirStat::new(), 0));
+    }
+
+    members(path, archive, |member, _| {
+        if member.kind == EntryType::Dir {
+            add_dir(&mut dirs, &member.name);
+            return;
+        }
+
+        let parent = member_parent(&member.name);
+        add_dir(&mut dirs, parent);
+
+        let (stat, entries) = dirs.entry(parent.to_owned()).or_default();
+        *entries += 1;
+        stat.apparent_size += member.size;
+
+        match member.kind {
+            EntryType::File => {
+                stat.files += 1;
+                *stat.extensions.entry(extension(&member.name)).or_default() += 1;
+
+                if member.size == 0 {
+                    let path = member_path(path, &member.name);
+                    stat.mark(Mark::EmptyFile, &path, options);
+                }
+            }
+            EntryType::Symlink => stat.symlinks += 1,
+            #[cfg(unix)]
+            EntryType::Block => stat.blocks += 1,
+            #[cfg(unix)]
+            EntryType::Char => stat.chars += 1,
+            #[cfg(unix)]
+            EntryType::Fifo => stat.fifos += 1,
+            _ => (),
+        }
+    })?;
+
+    let empty = dirs
+        .iter()
+        .filter(|(name, (_, entries))| !name.is_empty() && *entries == 0)
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+
+    for name in empty {
+        let (stat, _) = dirs.entry(member_parent(&name).to_owned()).or_default();
+        stat.mark(Mark::EmptyDir, &member_path(path, &name), options);
+    }
+
+    if options.cumulative {
+        let sizes = dirs
+            .iter()
+            .map(|(name, (stat, _))| (name.clone(), stat.apparent_size))
+            .collect::<Vec<_>>();
+
+        for (name, size) in sizes {
+            let mut dir = name.as_str();
+
+            loop {
+                dirs.entry(dir.to_owned()).or_default().0.tree_apparent_size += size;
+
+                if dir.is_empty() {
+                    break;
+                }
+                dir = member_parent(dir);
+            }
         }
+    }
+
+    Ok(dirs
+        .into_iter()
+        .map(|(name, (stat, _))| (name, stat))
+        .collect())
+}
+
+/// Returns the extension of `path`, or an empty string if it has none.
+pub fn extension(path: impl AsRef<Path>) -> String {
+    path.as_ref()
+        .extension()
+        .map(|ext| ext.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+pub fn stdin(options: &Options) -> io::Result<FileStat> {
+    read(io::stdin().lock(), options)
+}
+
+/// Counts the content of `reader`, decompressing it if needed.
+fn read(mut reader: impl Read, options: &Options) -> io::Result<FileStat> {
+    let head = read_head(&mut reader)?;
+
+    if let Some(compression) = options
+        .decompress
+        .then(|| Compression::detect(&head))
+        .flatten()
+    {
+        return decompress(head.as_slice().chain(reader), compression, options);
+    }
 
-        stat.lines += 1;
-        stat.bytes += len;
-        stat.chars += buf.chars().count();
-        stat.words += buf.split_whitespace().count();
+    stream(head, reader, options)
+}
+
+/// Counts a stream whose first bytes, read by [`read_head`], are `head`.
+fn stream(head: Vec<u8>, mut rest: impl Read, options: &Options) -> io::Result<FileStat> {
+    let (encoding, binary) = sniff(&head, options);
 
-        buf.clear();
+    if binary {
+        let rest = io::copy(&mut rest, &mut io::sink())?;
+        return Ok(FileStat::binary(head.len() + rest as usize, options));
     }
 
+    scan(
+        head.as_slice().chain(rest),
+        SCAN_BUFFER_SIZE,
+        encoding,
+        options,
+    )
+}
+
+/// Counts the decompressed content of `reader`.
+fn decompress(
+    reader: impl Read,
+    compression: Compression,
+    options: &Options,
+) -> io::Result<FileStat> {
+    let mut compressed = Counted {
+        inner: reader,
+        bytes: 0,
+    };
+
+    let mut decoder = compression.decoder(&mut compressed)?;
+    let head = read_head(&mut decoder)?;
+    let mut stat = stream(head, decoder, options)?;
+
+    stat.compressed_bytes = Some(compressed.bytes);
     Ok(stat)
 }
+
+/// Reader counting the bytes read from the reader it wraps.
+struct Counted<R> {
+    inner: R,
+    bytes: usize,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.bytes += len;
+        Ok(len)
+    }
+}
+
+/// Reads the start of `reader` to tell its encoding and whether it's binary.
+fn read_head(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut head = vec![];
+
+    reader
+        .take(BINARY_SNIFF_SIZE as u64)
+        .read_to_end(&mut head)?;
+
+    Ok(head)
+}
+
+/// Returns the encoding of a file from `head`, its start, and whether it's a
+/// binary file to leave uncounted.
+fn sniff(head: &[u8], options: &Options) -> (Encoding, bool) {
+    let encoding = options.encoding.detect(head);
+
+    // UTF-16 text is full of NUL bytes.
+    let binary = options.binary != BinaryMode::Count
+        && !matches!(encoding, Encoding::Utf16Le | Encoding::Utf16Be)
+        && is_binary(head);
+
+    (encoding, binary)
+}
+
+/// Tells whether `head`, the start of a file, is binary rather than text.
+fn is_binary(head: &[u8]) -> bool {
+    let control = count_matching(head, |b| {
+        (b < 0x20) & !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b) | (b == 0x7f)
+    });
+
+    head.contains(&0) || control * 10 > head.len()
+}
+
+/// Counts `reader` in blocks of up to `buf_len` bytes, without splitting it
+/// into lines. Text in other encodings than UTF-8 is counted once converted to
+/// UTF-8, except for its bytes.
+fn scan(
+    reader: impl Read,
+    buf_len: usize,
+    encoding: Encoding,
+    options: &Options,
+) -> io::Result<FileStat> {
+    let mut scanner = Scanner::new(options);
+
+    if encoding == Encoding::Utf8 {
+        scanner.read(reader, buf_len)?;
+    } else {
+        scanner.read_decoded(reader, buf_len, encoding)?;
+    }
+
+    scanner.finish()
+}
+
+/// Counts a file of `len` bytes by splitting it into chunks counted in parallel
+/// on the current thread pool. Produces the same results as [`scan`].
+fn scan_chunked(path: &Path, len: u64, chunk_size: u64, options: &Options) -> io::Result<FileStat> {
+    let mut f = fs::File::open(path)?;
+    let words = Words::new(&options.word_mode);
+    let mut starts = vec![(0, false)];
+
+    for i in 1..len.div_ceil(chunk_size) {
+        let Some(start) = chunk_start(&mut f, i * chunk_size, words.as_ref())? else {
+            continue;
+        };
+
+        if start.0 < len && starts.last().is_some_and(|last| last.0 < start.0) {
+            starts.push(start);
+        }
+    }
+
+    let chunks = starts
+        .iter()
+        .enumerate()
+        .map(|(i, &(start, in_word))| {
+            let end = starts.get(i + 1).map_or(len, |next| next.0);
+            (start, end, in_word, end == len)
+        })
+        .collect::<Vec<_>>();
+
+    chunks
+        .into_par_iter()
+        .map(|(start, end, in_word, last)| {
+            // Each chunk needs its own handle, clones share the file offset.
+            let mut f = fs::File::open(path)?;
+            f.seek(SeekFrom::Start(start))?;
+
+            let mut scanner = Scanner {
+                in_word,
+                ..Scanner::new(options)
+            };
+            scanner.read(f.take(end - start), SCAN_BUFFER_SIZE)?;
+
+            if last {
+                scanner.finish()
+            } else {
+                scanner.finish_chunk()
+            }
+        })
+        .try_reduce(FileStat::new, |mut a, b| {
+            a += b;
+            Ok(a)
+        })
+}
+
+/// Moves `pos` forward to the start of the next UTF-8 sequence, so that no
+/// character is split between two chunks. Also returns whether the character
+/// before it is part of a word.
+///
+/// With `words`, `pos` is moved past the next character no word spans, found
+/// within [`CHUNK_SEARCH`] bytes, or `None` is returned if there is none.
+fn chunk_start(
+    f: &mut fs::File,
+    pos: u64,
+    words: Option<&Words>,
+) -> io::Result<Option<(u64, bool)>> {
+    let from = pos.saturating_sub(4);
+    let mut buf = Vec::with_capacity(8);
+
+    f.seek(SeekFrom::Start(from))?;
+    f.take(8).read_to_end(&mut buf)?;
+
+    let boundary = ((pos - from) as usize).min(buf.len());
+    let start = boundary
+        + buf[boundary..]
+            .iter()
+            .take(3)
+            .take_while(|&&b| (b as i8) < -0x40)
+            .count();
+
+    let Some(words) = words else {
+        // Invalid UTF-8 before the boundary fails the chunk it belongs to anyway.
+        let in_word = String::from_utf8_lossy(&buf[..start])
+            .chars()
+            .next_back()
+            .is_some_and(|c| !c.is_whitespace());
+
+        return Ok(Some((from + start as u64, in_word)));
+    };
+
+    let start = from + start as u64;
+    buf.clear();
+    f.seek(SeekFrom::Start(start))?;
+    f.take(CHUNK_SEARCH).read_to_end(&mut buf)?;
+
+    let valid = std::str::from_utf8(&buf).map_or_else(|e| e.valid_up_to(), str::len);
+    let text = String::from_utf8_lossy(&buf[..valid]);
+
+    Ok(text
+        .char_indices()
+        .find(|&(_, c)| words.breaks_at(c))
+        .map(|(i, c)| (start + (i + c.len_utf8()) as u64, false)))
+}
+
+/// Incremental counter over a byte stream fed in arbitrarily sized chunks.
+#[derive(Debug, Default)]
+struct Scanner {
+    stat: FileStat,
+    /// Whether the last character seen was part of a word.
+    in_word: bool,
+    /// Last byte seen, used to count a final line without a line feed.
+    last: Option<u8>,
+    /// Leading bytes of a UTF-8 sequence split between two chunks.
+    partial: [u8; 4],
+    partial_len: usize,
+    /// The line being scanned, only tracked for line statistics.
+    line: Option<Lines<Line>>,
+    /// Only tracked for grapheme and display width statistics.
+    clusters: Option<Carry>,
+    /// Only tracked for line ending counts.
+    endings: Option<Endings>,
+    /// The whitespace of the line being scanned, only tracked for whitespace
+    /// counts.
+    spacing: Option<Lines<Spacing>>,
+    /// Words told apart by other means than whitespace, if enabled.
+    words: Option<Words>,
+    metrics: Vec<Counter>,
+}
+
+impl Scanner {
+    fn new(options: &Options) -> Self {
+        Self {
+            stat: FileStat {
+                computed: Computed {
+                    line_stats: options.line_stats,
+                    unicode_stats: options.unicode_stats,
+                    line_endings: options.line_endings,
+                    whitespace_stats: options.whitespace_stats,
+                },
+                ..FileStat::default()
+            },
+            line: options.line_stats.then(Lines::default),
+            clusters: options.unicode_stats.then(Carry::default),
+            endings: options.line_endings.then(Endings::default),
+            spacing: options.whitespace_stats.then(Lines::default),
+            words: Words::new(&options.word_mode),
+            metrics: options
+                .metrics
+                .iter()
+                .map(|m| Counter::new(m.as_ref()))
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    fn feed(&mut self, mut chunk: &[u8]) -> io::Result<()> {
+        let Some(&last) = chunk.last() else {
+            return Ok(());
+        };
+
+        self.stat.bytes += chunk.len();
+        self.last = Some(last);
+
+        if self.partial_len > 0 {
+            let width = utf8_width(self.partial[0]);
+            let take = (width - self.partial_len).min(chunk.len());
+
+            self.partial[self.partial_len..self.partial_len + take].copy_from_slice(&chunk[..take]);
+            self.partial_len += take;
+            chunk = &chunk[take..];
+
+            if self.partial_len < width {
+                return Ok(());
+            }
+
+            let partial = self.partial;
+            self.partial_len = 0;
+
+            let text = str::from_utf8(&partial[..width]).map_err(|_| invalid_utf8())?;
+            self.count_text(text);
+        }
+
+        let text = match simdutf8::compat::from_utf8(chunk) {
+            Ok(text) => text,
+            Err(e) if e.error_len().is_none() => {
+                let (valid, rest) = chunk.split_at(e.valid_up_to());
+
+                self.partial[..rest.len()].copy_from_slice(rest);
+                self.partial_len = rest.len();
+
+                simdutf8::basic::from_utf8(valid).map_err(|_| invalid_utf8())?
+            }
+            Err(_) => return Err(invalid_utf8()),
+        };
+
+        self.count_text(text);
+
+        Ok(())
+    }
+
+    fn count_text(&mut self, mut text: &str) {
+        while !text.is_empty() {
+            let mut end = text.len().min(SCAN_BLOCK_SIZE);
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            let (block, rest) = text.split_at(end);
+            let bytes = block.as_bytes();
+
+            self.stat.lines += count_newlines(bytes);
+            match &mut self.words {
+                Some(words) => words.feed(block, &mut self.stat),
+                None => self.stat.words += count_word_starts(bytes, self.in_word),
+            }
+
+            if block.is_ascii() {
+                self.stat.chars += bytes.len();
+                self.in_word = !is_ascii_space(bytes[bytes.len() - 1]);
+            } else {
+                self.stat.chars += count_utf8_lead_bytes(bytes);
+                self.in_word = block
+                    .chars()
+                    .next_back()
+                    .is_some_and(|c| !c.is_whitespace());
+            }
+
+            if let Some(line) = &mut self.line {
+                line.feed(block, &mut self.stat);
+            }
+
+            if let Some(clusters) = &mut self.clusters {
+                clusters.feed::<Clusters>(block, &mut self.stat);
+            }
+
+            if let Some(endings) = &mut self.endings {
+                endings.feed(bytes);
+            }
+
+            if let Some(spacing) = &mut self.spacing {
+                spacing.feed(block, &mut self.stat);
+            }
+
+            for metric in &mut self.metrics {
+                if let Counter::Custom(counter) = metric {
+                    counter.feed(bytes);
+                }
+            }
+
+            text = rest;
+        }
+    }
+
+    fn read(&mut self, mut reader: impl Read, buf_len: usize) -> io::Result<()> {
+        let mut buf = vec![0; buf_len];
+
+        loop {
+            let len = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            self.feed(&buf[..len])?;
+        }
+
+        Ok(())
+    }
+
+    /// Like [`Scanner::read`], for text in another encoding than UTF-8, whose
+    /// bytes are counted before decoding.
+    fn read_decoded(
+        &mut self,
+        mut reader: impl Read,
+        buf_len: usize,
+        encoding: Encoding,
+    ) -> io::Result<()> {
+        let mut decoder = match encoding {
+            Encoding::Utf16Le => Some(encoding_rs::UTF_16LE.new_decoder_with_bom_removal()),
+            Encoding::Utf16Be => Some(encoding_rs::UTF_16BE.new_decoder_with_bom_removal()),
+            Encoding::Windows1252 => Some(encoding_rs::WINDOWS_1252.new_decoder()),
+            Encoding::Latin1 => None,
+            Encoding::Auto | Encoding::Utf8 => unreachable!("UTF-8 needs no decoding"),
+        };
+
+        let mut buf = vec![0; buf_len];
+        // Every encoding takes at most three bytes of UTF-8 per byte.
+        let mut text = String::with_capacity(3 * buf_len);
+        let mut bytes = 0;
+
+        loop {
+            let len = match reader.read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            bytes += len;
+            let mut input = &buf[..len];
+
+            match &mut decoder {
+                Some(decoder) => loop {
+                    let (result, read) =
+                        decoder.decode_to_string_without_replacement(input, &mut text, len == 0);
+                    input = &input[read..];
+
+                    match result {
+                        DecoderResult::InputEmpty => break,
+                        DecoderResult::OutputFull => {
+                            self.feed(text.as_bytes())?;
+                            text.clear();
+                        }
+                        DecoderResult::Malformed(..) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!(
+                                    "stream did not contain valid {}",
+                                    decoder.encoding().name()
+                                ),
+                            ));
+                        }
+                    }
+                },
+                None => text.extend(input.iter().map(|&b| char::from(b))),
+            }
+
+            self.feed(text.as_bytes())?;
+            text.clear();
+
+            if len == 0 {
+                self.stat.bytes = bytes;
+                return Ok(());
+            }
+        }
+    }
+
+    fn finish(mut self) -> io::Result<FileStat> {
+        if let Some(endings) = &self.endings {
+            endings.end(&mut self.stat, self.last);
+        }
+
+        if self.last.is_some_and(|b| b != b'\n') {
+            self.stat.lines += 1;
+
+            if let Some(line) = &mut self.line {
+                line.end(&mut self.stat);
+            }
+
+            if let Some(spacing) = &mut self.spacing {
+                spacing.end(&mut self.stat);
+            }
+        }
+
+        if let Some(clusters) = &mut self.clusters {
+            clusters.end::<Clusters>(&mut self.stat);
+        }
+
+        if let Some(words) = &mut self.words {
+            words.end(&mut self.stat);
+        }
+
+        self.finish_chunk()
+    }
+
+    /// Like [`Scanner::finish`], but for a chunk that isn't the end of its
+    /// file, so an unterminated last line doesn't count as a line.
+    fn finish_chunk(mut self) -> io::Result<FileStat> {
+        if self.partial_len > 0 {
+            return Err(invalid_utf8());
+        }
+
+        self.stat.metrics = self
+            .metrics
+            .iter_mut()
+            .map(|m| m.finish(&self.stat))
+            .collect();
+
+        Ok(self.stat)
+    }
+}
+
+/// Counting state of a metric in a [`Scanner`].
+#[derive(Debug)]
+enum Counter {
+    /// A built-in count, taken from the counts of the scanner.
+    Count(Count),
+    Custom(Box<dyn MetricCounter>),
+}
+
+impl Counter {
+    fn new(metric: &dyn Metric) -> Self {
+        match metric.count() {
+            Some(count) => Self::Count(count),
+            None => Self::Custom(metric.counter()),
+        }
+    }
+
+    /// Returns the value of the metric, `stat` holding the counts of the input.
+    fn finish(&mut self, stat: &FileStat) -> MetricValue {
+        match self {
+            Self::Count(count) => MetricValue::sum(count.get(stat)),
+            Self::Custom(counter) => counter.finish(),
+        }
+    }
+}
+
+/// Counter of a [`Count`] fed on its own, outside of a [`Scanner`].
+#[derive(Debug)]
+pub(crate) struct CountCounter {
+    count: Count,
+    scanner: Scanner,
+}
+
+impl CountCounter {
+    pub(crate) fn new(count: Count) -> Self {
+        Self {
+            count,
+            scanner: Scanner::default(),
+        }
+    }
+}
+
+impl MetricCounter for CountCounter {
+    fn feed(&mut self, chunk: &[u8]) {
+        // Chunks are UTF-8 text, which the scanner only fails to count when
+        // it isn't.
+        let _ = self.scanner.feed(chunk);
+    }
+
+    fn finish(&mut self) -> MetricValue {
+        let stat = std::mem::take(&mut self.scanner).finish();
+        MetricValue::sum(stat.map_or(0, |stat| self.count.get(&stat)))
+    }
+}
+
+/// Statistic of lines, fed the characters of each line but its terminator.
+trait LineStat: Default {
+    fn push(&mut self, c: char);
+
+    /// Adds the statistic of the line to `stat`.
+    fn end(self, stat: &mut FileStat);
+}
+
+/// Splits a stream of text into lines for a [`LineStat`].
+#[derive(Debug, Default)]
+struct Lines<L> {
+    /// The line being scanned.
+    line: L,
+    /// Whether the last character seen is a carriage return, which isn't part
+    /// of the line if a line feed follows.
+    cr: bool,
+}
+
+impl<L: LineStat> Lines<L> {
+    fn feed(&mut self, text: &str, stat: &mut FileStat) {
+        for (i, segment) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.end(stat);
+            }
+
+            for c in segment.chars() {
+                if self.cr {
+                    self.line.push('\r');
+                }
+
+                self.cr = c == '\r';
+                if !self.cr {
+                    self.line.push(c);
+                }
+            }
+        }
+    }
+
+    fn end(&mut self, stat: &mut FileStat) {
+        std::mem::take(self).line.end(stat);
+    }
+}
+
+/// Length of the line being scanned.
+#[derive(Debug, Default)]
+struct Line {
+    chars: usize,
+    width: usize,
+}
+
+impl LineStat for Line {
+    fn push(&mut self, c: char) {
+        self.chars += 1;
+        self.width += match c {
+            '\t' => 8 - self.width % 8,
+            c => c.width().unwrap_or(0),
+        };
+    }
+
+    fn end(self, stat: &mut FileStat) {
+        stat.max_line_chars = stat.max_line_chars.max(self.chars);
+        stat.max_line_width = stat.max_line_width.max(self.width);
+        stat.line_lengths.push(self.chars);
+    }
+}
+
+/// Whitespace of the line being scanned.
+#[derive(Debug, Default)]
+struct Spacing {
+    first: Option<char>,
+    /// Whether the line has characters other than whitespace.
+    text: bool,
+    /// Whether the last character seen is whitespace.
+    trailing: bool,
+}
+
+impl LineStat for Spacing {
+    fn push(&mut self, c: char) {
+        self.first.get_or_insert(c);
+        self.trailing = c.is_whitespace();
+        self.text |= !self.trailing;
+    }
+
+    fn end(self, stat: &mut FileStat) {
+        match self.first {
+            None => stat.blank_lines += 1,
+            Some(_) if !self.text => stat.whitespace_lines += 1,
+            Some('\t') => stat.tab_indented_lines += 1,
+            Some(' ') => stat.space_indented_lines += 1,
+            Some(_) => (),
+        }
+
+        if self.trailing {
+            stat.trailing_whitespace_lines += 1;
+        }
+    }
+}
+
+/// Line terminators seen so far.
+#[derive(Debug, Default)]
+struct Endings {
+    /// Number of carriage returns, whether followed by a line feed or not.
+    cr: usize,
+    crlf: usize,
+    /// Whether the last byte seen is a carriage return.
+    last_cr: bool,
+}
+
+impl Endings {
+    fn feed(&mut self, bytes: &[u8]) {
+        self.cr += count_matching(bytes, |b| b == b'\r');
+        self.crlf += count_crlf(bytes) + (self.last_cr && bytes[0] == b'\n') as usize;
+        self.last_cr = bytes[bytes.len() - 1] == b'\r';
+    }
+
+    /// Sets the line ending counts of `stat`, whose lines must not yet include
+    /// a last line without a line feed.
+    fn end(&self, stat: &mut FileStat, last: Option<u8>) {
+        stat.lf_lines = stat.lines - self.crlf;
+        stat.crlf_lines = self.crlf;
+        stat.cr_lines = self.cr - self.crlf;
+
+        let kinds = [stat.lf_lines, stat.crlf_lines, stat.cr_lines];
+        stat.mixed_endings = (kinds.iter().filter(|&&n| n > 0).count() > 1) as usize;
+        stat.missing_final_newline = last.is_some_and(|b| b != b'\n' && b != b'\r') as usize;
+    }
+}
+
+/// Segmentation of a stream of text by a [`Carry`].
+trait Segmentation {
+    /// Returns the start of the last segments of `text`, which the text after
+    /// it may still extend.
+    fn kept(text: &str) -> usize;
+
+    /// Counts the segments of `text`, except the first one if `counted` is
+    /// set, which is then cleared, as it continues the last segment counted.
+    fn count(text: &str, counted: &mut bool, stat: &mut FileStat);
+
+    /// Whether the last character of `text`, at `last`, continues a segment
+    /// counted with the text before it.
+    fn continued(text: &str, last: usize) -> bool;
+}
+
+/// The end of a stream of text held back until the text after it tells where
+/// its last segments end.
+#[derive(Debug, Default)]
+struct Carry {
+    text: String,
+    /// Whether the first segment of `text` continues one already counted, after
+    /// a carry longer than [`MAX_CARRY`] was flushed.
+    counted: bool,
+}
+
+impl Carry {
+    fn feed<S: Segmentation>(&mut self, text: &str, stat: &mut FileStat) {
+        self.text.push_str(text);
+
+        let kept = S::kept(&self.text);
+        S::count(&self.text[..kept], &mut self.counted, stat);
+        self.text.drain(..kept);
+
+        if self.text.len() > MAX_CARRY {
+            // Only the last character is kept, to join what follows, so the
+            // carry is segmented in constant time per block.
+            let last = self.text.char_indices().next_back().map_or(0, |(i, _)| i);
+
+            S::count(&self.text[..last], &mut self.counted, stat);
+            self.counted = S::continued(&self.text, last);
+            self.text.drain(..last);
+        }
+    }
+
+    fn end<S: Segmentation>(&mut self, stat: &mut FileStat) {
+        S::count(&self.text, &mut self.counted, stat);
+        self.text.clear();
+    }
+}
+
+/// Grapheme cluster segmentation, for the grapheme count and display width.
+struct Clusters;
+
+impl Segmentation for Clusters {
+    fn kept(text: &str) -> usize {
+        text.grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    /// Also counts the width of the clusters, that of a cluster continued
+    /// included.
+    fn count(text: &str, counted: &mut bool, stat: &mut FileStat) {
+        for g in text.graphemes(true) {
+            if !std::mem::take(counted) {
+                stat.graphemes += 1;
+            }
+
+            if !g.chars().all(char::is_control) {
+                stat.width += g.width();
+            }
+        }
+    }
+
+    fn continued(_: &str, _: usize) -> bool {
+        // The last character of a cluster held back always belongs to it.
+        true
+    }
+}
+
+/// Word counting of a stream of text for the word modes other than
+/// [`WordMode::Whitespace`].
+#[derive(Debug)]
+enum Words {
+    /// The last two segments seen. Whether a boundary is kept depends on the
+    /// two characters after it, as in `a.b` being a single word.
+    Unicode(Carry),
+    Identifier {
+        /// Whether the current run of identifier bytes contains an identifier,
+        /// a run like `9lives` holding `lives`.
+        in_word: bool,
+    },
+    Delimiters {
+        delimiters: Vec<char>,
+        in_word: bool,
+    },
+    Regex(LineMatcher<Regex>),
+}
+
+impl Words {
+    fn new(mode: &WordMode) -> Option<Self> {
+        match mode {
+            WordMode::Whitespace => None,
+            WordMode::Unicode => Some(Self::Unicode(Carry::default())),
+            WordMode::Identifier => Some(Self::Identifier { in_word: false }),
+            WordMode::Delimiters(delimiters) => Some(Self::Delimiters {
+                delimiters: delimiters.clone(),
+                in_word: false,
+            }),
+            WordMode::Regex(regex) => Some(Self::Regex(LineMatcher::new(
+                regex.clone(),
+                PatternMode::Matches,
+            ))),
+        }
+    }
+
+    fn feed(&mut self, text: &str, stat: &mut FileStat) {
+        match self {
+            Self::Unicode(carry) => carry.feed::<UnicodeWords>(text, stat),
+            Self::Identifier { in_word } => {
+                for &b in text.as_bytes() {
+                    let is_start = b.is_ascii_alphabetic() || b == b'_';
+
+                    if is_start && !*in_word {
+                        stat.words += 1;
+                    }
+
+                    *in_word = is_start || (*in_word && b.is_ascii_digit());
+                }
+            }
+            Self::Delimiters {
+                delimiters,
+                in_word,
+            } => {
+                for c in text.chars() {
+                    let is_delimiter = c == '\n' || c == '\r' || delimiters.contains(&c);
+
+                    if !is_delimiter && !*in_word {
+                        stat.words += 1;
+                    }
+
+                    *in_word = !is_delimiter;
+                }
+            }
+            Self::Regex(matcher) => matcher.feed(text.as_bytes()),
+        }
+    }
+
+    /// Whether no word spans `c`, so that counting can start after it without
+    /// the state before it. Only with the identifier and delimiter modes.
+    fn breaks_at(&self, c: char) -> bool {
+        match self {
+            Self::Identifier { .. } => !(c.is_ascii_alphanumeric() || c == '_'),
+            Self::Delimiters { delimiters, .. } => {
+                c == '\n' || c == '\r' || delimiters.contains(&c)
+            }
+            Self::Unicode { .. } | Self::Regex(_) => false,
+        }
+    }
+
+    fn end(&mut self, stat: &mut FileStat) {
+        match self {
+            Self::Unicode(carry) => carry.end::<UnicodeWords>(stat),
+            Self::Regex(matcher) => stat.words += matcher.finish().get(),
+            Self::Identifier { .. } | Self::Delimiters { .. } => (),
+        }
+    }
+}
+
+/// Word boundary segmentation, counting the segments with a letter or a digit.
+struct UnicodeWords;
+
+impl Segmentation for UnicodeWords {
+    fn kept(text: &str) -> usize {
+        text.split_word_bound_indices()
+            .nth_back(1)
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn count(text: &str, counted: &mut bool, stat: &mut FileStat) {
+        let mut segments = text.split_word_bounds();
+
+        if !text.is_empty() && std::mem::take(counted) {
+            segments.next();
+        }
+
+        stat.words += segments.filter(|s| is_word(s)).count();
+    }
+
+    fn continued(text: &str, last: usize) -> bool {
+        let start = text
+            .split_word_bound_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i);
+
+        start < last && is_word(&text[start..last])
+    }
+}
+
+fn is_word(segment: &str) -> bool {
+    segment.chars().any(char::is_alphanumeric)
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "stream did not contain valid UTF-8",
+    )
+}
+
+/// Returns the length of a UTF-8 sequence from its leading byte.
+fn utf8_width(lead: u8) -> usize {
+    match lead {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        _ => 4,
+    }
+}
+
+/// Matches [`char::is_whitespace`] for ASCII, which unlike
+/// [`u8::is_ascii_whitespace`] includes the vertical tab.
+fn is_ascii_space(b: u8) -> bool {
+    (b == b' ') | (b.wrapping_sub(b'\t') < 5)
+}
+
+// The counting loops below are written as branch-free sums over plain slices,
+// accumulated in bytes over runs of at most 255 elements, so that LLVM turns
+// them into SIMD code.
+
+fn count_matching(bytes: &[u8], f: impl Fn(u8) -> bool) -> usize {
+    bytes
+        .chunks(u8::MAX as usize)
+        .map(|run| run.iter().fold(0u8, |n, &b| n + f(b) as u8) as usize)
+        .sum()
+}
+
+fn count_newlines(bytes: &[u8]) -> usize {
+    count_matching(bytes, |b| b == b'\n')
+}
+
+fn count_utf8_lead_bytes(bytes: &[u8]) -> usize {
+    count_matching(bytes, |b| (b as i8) >= -0x40)
+}
+
+/// Counts the carriage return and line feed pairs in `bytes`.
+fn count_crlf(bytes: &[u8]) -> usize {
+    let runs = u8::MAX as usize;
+
+    bytes
+        .chunks(runs)
+        .zip(bytes[1..].chunks(runs))
+        .map(|(prev, next)| {
+            prev.iter().zip(next).fold(0u8, |n, (&prev, &b)| {
+                n + ((prev == b'\r') & (b == b'\n')) as u8
+            }) as usize
+        })
+        .sum()
+}
+
+/// Counts the words starting in `bytes`, which must hold complete UTF-8
+/// sequences. The character before `bytes` is taken as a space unless
+/// `in_word` is set.
+fn count_word_starts(bytes: &[u8], in_word: bool) -> usize {
+    // Continuation bytes are never ASCII spaces, so a word starts at every
+    // non-space byte following an ASCII space byte, as long as the text has no
+    // Unicode spaces.
+    let first = !in_word && !is_ascii_space(bytes[0]);
+    let runs = u8::MAX as usize;
+    let mut words = first as usize
+        + bytes
+            .chunks(runs)
+            .zip(bytes[1..].chunks(runs))
+            .map(|(prev, next)| {
+                prev.iter().zip(next).fold(0u8, |n, (&prev, &b)| {
+                    n + (is_ascii_space(prev) & !is_ascii_space(b)) as u8
+                }) as usize
+            })
+            .sum::<usize>();
+
+    if bytes.is_ascii() || !has_unicode_space(bytes) {
+        return words;
+    }
+
+    for (i, _) in bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| matches!(b, 0xc2 | 0xe1..=0xe3))
+    {
+        let len = unicode_space_len(&bytes[i..]);
+        if len == 0 {
+            continue;
+        }
+
+        // Undo the word start counted at the space itself, if any.
+        let counted = if i == 0 {
+            first
+        } else {
+            is_ascii_space(bytes[i - 1])
+        };
+        if counted {
+            words -= 1;
+        }
+
+        // The character after it follows a continuation byte, so it wasn't
+        // counted even if it starts a word.
+        let next = &bytes[i + len..];
+        if next.first().is_some_and(|&b| !is_ascii_space(b)) && unicode_space_len(next) == 0 {
+            words += 1;
+        }
+    }
+
+    words
+}
+
+/// Returns whether `bytes` contains a non-ASCII whitespace character. Same as
+/// looking for [`unicode_space_len`] at every position, but vectorizable.
+fn has_unicode_space(bytes: &[u8]) -> bool {
+    let triples = bytes
+        .iter()
+        .zip(&bytes[1..])
+        .zip(bytes.get(2..).unwrap_or_default());
+
+    // Continuation bytes are at least 0x80, so `c <= 0x8a` covers 0x80..=0x8a.
+    let found = triples.fold(false, |found, ((&a, &b), &c)| {
+        found
+            | (a == 0xc2) & ((b == 0x85) | (b == 0xa0))
+            | (a == 0xe1) & (b == 0x9a) & (c == 0x80)
+            | (a == 0xe2) & (b == 0x80) & ((c <= 0x8a) | (c == 0xa8) | (c == 0xa9) | (c == 0xaf))
+            | (a == 0xe2) & (b == 0x81) & (c == 0x9f)
+            | (a == 0xe3) & (b == 0x80) & (c == 0x80)
+    });
+
+    // The triples miss a two byte space at the very end.
+    found || unicode_space_len(&bytes[bytes.len().saturating_sub(2)..]) != 0
+}
+
+/// Returns the length of the non-ASCII whitespace character `bytes` starts
+/// with, or zero if it doesn't start with one.
+fn unicode_space_len(bytes: &[u8]) -> usize {
+    match bytes {
+        // U+0085, U+00A0
+        [0xc2, 0x85 | 0xa0, ..] => 2,
+        // U+1680
+        [0xe1, 0x9a, 0x80, ..] => 3,
+        // U+2000..U+200A, U+2028, U+2029, U+202F
+        [0xe2, 0x80, 0x80..=0x8a | 0xa8 | 0xa9 | 0xaf, ..] => 3,
+        // U+205F
+        [0xe2, 0x81, 0x9f, ..] => 3,
+        // U+3000
+        [0xe3, 0x80, 0x80, ..] => 3,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metric::{MAX_MATCH_LINE, Merge, Pattern};
+
+    /// Writes `contents` to a file of the temporary directory, unique to this
+    /// process and `name`.
+    fn temp_file(name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("lwc-{}-{name}", std::process::id()));
+        fs::write(&path, contents).expect("temporary file");
+        path
+    }
+
+    fn counts(stat: &FileStat) -> (usize, usize, usize, usize) {
+        (stat.lines, stat.words, stat.chars, stat.bytes)
+    }
+
+    /// Calls `f` with `text` in blocks of `block` bytes, or a little more not
+    /// to split a character.
+    fn feed_in_blocks(text: &str, block: usize, mut f: impl FnMut(&str)) {
+        let mut rest = text;
+
+        while !rest.is_empty() {
+            let mut end = rest.len().min(block);
+            while !rest.is_char_boundary(end) {
+                end += 1;
+            }
+
+            f(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+
+    /// Counts the Unicode words of `text`, fed in blocks of `block` bytes.
+    fn unicode_words(text: &str, block: usize) -> usize {
+        let mut words = Words::new(&WordMode::Unicode).expect("unicode words");
+        let mut stat = FileStat::default();
+
+        feed_in_blocks(text, block, |block| words.feed(block, &mut stat));
+        words.end(&mut stat);
+
+        stat.words
+    }
+
+    #[test]
+    fn unicode_words_across_blocks() {
+        assert_eq!(unicode_words("a.b can't stop, 3.14 é", 1), 5);
+        assert_eq!(unicode_words("a.b can't stop, 3.14 é", SCAN_BLOCK_SIZE), 5);
+    }
+
+    #[test]
+    fn unicode_words_huge_token() {
+        let token = "a".repeat(2 * 1024 * 1024);
+
+        assert_eq!(unicode_words(&token, SCAN_BLOCK_SIZE), 1);
+        assert_eq!(
+            unicode_words(&format!("one {token}. {token} two"), SCAN_BLOCK_SIZE),
+            4
+        );
+    }
+
+    /// Counts the grapheme clusters and width of `text`, fed in blocks of
+    /// `block` bytes.
+    fn clusters(text: &str, block: usize) -> (usize, usize) {
+        let mut clusters = Carry::default();
+        let mut stat = FileStat::default();
+
+        feed_in_blocks(text, block, |block| {
+            clusters.feed::<Clusters>(block, &mut stat)
+        });
+        clusters.end::<Clusters>(&mut stat);
+
+        (stat.graphemes, stat.width)
+    }
+
+    #[test]
+    fn graphemes_huge_cluster() {
+        let cluster = format!("a{}", "\u{301}".repeat(1024 * 1024));
+
+        assert_eq!(clusters(&cluster, SCAN_BLOCK_SIZE), (1, 1));
+        assert_eq!(
+            clusters(&format!("x{cluster} {cluster}y"), SCAN_BLOCK_SIZE),
+            (5, 5)
+        );
+    }
+
+    #[test]
+    fn chunked_word_modes() {
+        let text = "foo_1 9lives, a-b;c\r\n".repeat(100) + "é_x ";
+        let path = temp_file("chunked-word-modes", &text);
+
+        for word_mode in [
+            WordMode::Whitespace,
+            WordMode::Identifier,
+            WordMode::Delimiters(vec![',', ';', ' ']),
+        ] {
+            let options = Options::builder()
+                .word_mode(word_mode)
+                .build()
+                .expect("options");
+            let expected =
+                scan(text.as_bytes(), SCAN_BUFFER_SIZE, Encoding::Utf8, &options).expect("scan");
+
+            for chunk_size in [3, 7, 64] {
+                let stat = scan_chunked(&path, text.len() as u64, chunk_size, &options)
+                    .expect("chunked scan");
+                assert_eq!(counts(&stat), counts(&expected), "{options:?}");
+            }
+        }
+
+        fs::remove_file(path).expect("removed file");
+    }
+
+    #[test]
+    fn regex_words_strip_carriage_returns() {
+        let regex = Regex::new(r"\w+$").expect("regex");
+        let options = Options::builder()
+            .word_mode(WordMode::Regex(regex))
+            .build()
+            .expect("options");
+        let stat = scan(
+            &b"a b\r\nc\r\n"[..],
+            SCAN_BUFFER_SIZE,
+            Encoding::Utf8,
+            &options,
+        )
+        .expect("scan");
+
+        assert_eq!(stat.words, 2);
+    }
+
+    /// Counts a byte, in parts of a file merged with a function.
+    #[derive(Debug)]
+    struct ByteCount(u8);
+
+    #[derive(Debug)]
+    struct ByteCounter {
+        byte: u8,
+        count: usize,
+    }
+
+    impl Metric for ByteCount {
+        fn name(&self) -> &str {
+            "byte"
+        }
+
+        fn counter(&self) -> Box<dyn MetricCounter> {
+            Box::new(ByteCounter {
+                byte: self.0,
+                count: 0,
+            })
+        }
+
+        fn chunked(&self) -> bool {
+            true
+        }
+    }
+
+    impl MetricCounter for ByteCounter {
+        fn feed(&mut self, chunk: &[u8]) {
+            self.count += chunk.iter().filter(|&&b| b == self.byte).count();
+        }
+
+        fn finish(&mut self) -> MetricValue {
+            MetricValue::new(std::mem::take(&mut self.count), Merge::With(|a, b| a + b))
+        }
+    }
+
+    #[test]
+    fn chunked_metrics() {
+        let text = "foo bar\n".repeat(100);
+        let path = temp_file("chunked-metrics", &text);
+        let options = Options::builder()
+            .metric(ByteCount(b'o'))
+            .metric(Count::Lines)
+            .build()
+            .expect("options");
+
+        let stat = scan_chunked(&path, text.len() as u64, 7, &options).expect("chunked scan");
+        let values = stat.metrics.iter().map(|v| v.get()).collect::<Vec<_>>();
+        assert_eq!(values, [200, 100]);
+
+        fs::remove_file(path).expect("removed file");
+    }
+
+    #[test]
+    fn count_metrics() {
+        let options = Options::builder()
+            .metric(Count::Words)
+            .metric(Count::Bytes)
+            .build()
+            .expect("options");
+
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend("a b\nc".encode_utf16().flat_map(u16::to_le_bytes));
+        let stat = read(bytes.as_slice(), &options).expect("read");
+        let values = stat.metrics.iter().map(|v| v.get()).collect::<Vec<_>>();
+        assert_eq!(values, [3, bytes.len()]);
+
+        let mut counter = Count::Lines.counter();
+        counter.feed(b"a b\n");
+        counter.feed(b"c");
+        assert_eq!(counter.finish().get(), 2);
+    }
+
+    #[test]
+    fn serialize_computed_stats() {
+        let options = Options::builder()
+            .line_stats(true)
+            .build()
+            .expect("options");
+        let mut stat = read(b"a\n".as_slice(), &options).expect("read");
+        stat += FileStat::new();
+
+        let json = serde_json::to_value(&stat).expect("serialized");
+        assert_eq!(json["max_line_chars"], 1);
+        assert!(json.get("graphemes").is_none());
+        assert!(json.get("lf_lines").is_none());
+        assert!(json.get("blank_lines").is_none());
+    }
+
+    #[test]
+    fn patterns_on_long_lines() {
+        let regex = regex::bytes::Regex::new("b").expect("regex");
+        let options = Options::builder()
+            .metric(Pattern::new(regex.clone(), PatternMode::Lines))
+            .metric(Pattern::new(regex, PatternMode::Matches))
+            .build()
+            .expect("options");
+
+        let names = options
+            .metrics()
+            .iter()
+            .map(|m| m.name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["b (lines)", "b (matches)"]);
+
+        let text = format!("{}\r\n", "ab".repeat(MAX_MATCH_LINE)).repeat(2);
+        let stat = scan(text.as_bytes(), SCAN_BUFFER_SIZE, Encoding::Utf8, &options).expect("scan");
+        let values = stat.metrics.iter().map(|v| v.get()).collect::<Vec<_>>();
+
+        assert_eq!(values, [2, 2 * MAX_MATCH_LINE]);
+    }
+
+    #[test]
+    fn chunk_start_inside_utf8_sequence() {
+        let text = "aé b";
+        let path = temp_file("chunk-start", text);
+        let mut f = fs::File::open(&path).expect("opened file");
+
+        // Inside `é`, moved past it, in a word.
+        assert_eq!(
+            chunk_start(&mut f, 2, None).expect("start"),
+            Some((3, true))
+        );
+        // After the space, outside a word.
+        assert_eq!(
+            chunk_start(&mut f, 4, None).expect("start"),
+            Some((4, false))
+        );
+
+        fs::remove_file(path).expect("removed file");
+    }
+
+    #[test]
+    fn chunks_split_characters_and_words() {
+        let text = "héllo wörld 日本語\tcafé\n".repeat(20);
+        let path = temp_file("chunks-split", &text);
+        let options = Options::default();
+        let expected =
+            scan(text.as_bytes(), SCAN_BUFFER_SIZE, Encoding::Utf8, &options).expect("scan");
+
+        for chunk_size in 1..=9 {
+            let stat =
+                scan_chunked(&path, text.len() as u64, chunk_size, &options).expect("chunked scan");
+            assert_eq!(counts(&stat), counts(&expected), "chunks of {chunk_size}");
+        }
+
+        fs::remove_file(path).expect("removed file");
+    }
+
+    #[test]
+    fn crlf_split_between_blocks() {
+        let options = Options::builder()
+            .line_endings(true)
+            .build()
+            .expect("options");
+        let mut scanner = Scanner::new(&options);
+
+        scanner.feed(b"a\r").expect("fed");
+        scanner.feed(b"\nb\r\n").expect("fed");
+        let stat = scanner.finish().expect("finished");
+
+        assert_eq!(stat.lines, 2);
+        assert_eq!((stat.lf_lines, stat.crlf_lines, stat.cr_lines), (0, 2, 0));
+    }
+
+    #[test]
+    fn line_stats_with_crlf_split_between_blocks() {
+        let options = Options::builder()
+            .line_stats(true)
+            .whitespace_stats(true)
+            .build()
+            .expect("options");
+        let mut scanner = Scanner::new(&options);
+
+        for block in ["ab\r", "\n\r", "\n \r", "\nc"] {
+            scanner.feed(block.as_bytes()).expect("fed");
+        }
+        let stat = scanner.finish().expect("finished");
+
+        assert_eq!(stat.max_line_chars, 2);
+        assert_eq!(
+            stat.line_lengths.0,
+            BTreeMap::from([(0, 1), (1, 2), (2, 1)])
+        );
+        assert_eq!(stat.blank_lines, 1);
+        assert_eq!(stat.whitespace_lines, 1);
+        assert_eq!(stat.trailing_whitespace_lines, 1);
+    }
+
+    #[test]
+    fn utf16_with_bom() {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend("héllo wörld\n".encode_utf16().flat_map(u16::to_le_bytes));
+
+        let stat = read(bytes.as_slice(), &Options::default()).expect("read");
+
+        assert_eq!(counts(&stat), (1, 2, 12, 26));
+    }
+
+    #[test]
+    fn binary_heads() {
+        assert!(is_binary(b"text\0more text"));
+        assert!(is_binary(b"abcdefgh\x01\x02"));
+        assert!(!is_binary(b"abcdefghij\x01"));
+        assert!(!is_binary(b"a\tb\r\n\x0c\x1b[0m"));
+    }
+
+    #[test]
+    fn utf16_is_not_binary() {
+        let options = Options::builder()
+            .binary(BinaryMode::Bytes)
+            .build()
+            .expect("options");
+
+        let mut head = vec![0xff, 0xfe];
+        head.extend("text\n".encode_utf16().flat_map(u16::to_le_bytes));
+        assert!(head.contains(&0));
+
+        assert_eq!(sniff(&head, &options), (Encoding::Utf16Le, false));
+        assert_eq!(sniff(&head[2..], &options), (Encoding::Utf8, true));
+        assert_eq!(
+            sniff(&head[2..], &Options::default()),
+            (Encoding::Utf8, false)
+        );
+    }
+
+    #[test]
+    fn gzip_input() {
+        let text = b"one two\nthree\n";
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        io::Write::write_all(&mut encoder, text).expect("compressed");
+        let compressed = encoder.finish().expect("compressed");
+
+        let options = Options::builder()
+            .decompress(true)
+            .build()
+            .expect("options");
+        let stat = read(compressed.as_slice(), &options).expect("read");
+
+        assert_eq!(counts(&stat), (2, 3, 14, 14));
+        assert_eq!(stat.compressed_bytes, Some(compressed.len()));
+    }
+
+    #[test]
+    fn tar_members() {
+        let mut builder = tar::Builder::new(vec![]);
+
+        for (name, content) in [("a.txt", "x y\n"), ("dir/b.txt", "z\n")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, content.as_bytes())
+                .expect("appended member");
+        }
+
+        let path = temp_file("members.tar", builder.into_inner().expect("tar"));
+        let options = Options::builder().archives(true).build().expect("options");
+        let stats = count_many(&[&path], &options).expect("counted");
+
+        for (name, expected) in [("a.txt", (1, 2, 4, 4)), ("dir/b.txt", (1, 1, 2, 2))] {
+            match stats.get(&member_path(&path, name)) {
+                Some(Ok(Stat::File(stat))) => assert_eq!(counts(stat), expected, "{name}"),
+                stat => panic!("{name}: {stat:?}"),
+            }
+        }
+
+        assert_eq!(stats.len(), 2);
+        fs::remove_file(path).expect("removed file");
+    }
+
+    #[test]
+    fn directories_named_like_archives() {
+        let root = std::env::temp_dir().join(format!("lwc-{}-archive-dirs", std::process::id()));
+        let dir = root.join("old.tar");
+        fs::create_dir_all(&dir).expect("created directories");
+        fs::write(dir.join("f"), "x").expect("written file");
+
+        for (path, recursive) in [(&root, true), (&dir, false)] {
+            let options = Options::builder()
+                .which(Which::Dir)
+                .recursive(recursive)
+                .archives(true)
+                .build()
+                .expect("options");
+            let stats = count_many(&[path], &options).expect("counted");
+
+            match stats.get(&dir) {
+                Some(Ok(Stat::Dir(stat))) => assert_eq!(stat.files, 1),
+                stat => panic!("{stat:?}"),
+            }
+        }
+
+        fs::remove_dir_all(root).expect("removed directories");
+    }
+
+    #[test]
+    fn cumulative_sizes_count_hard_links_once() {
+        let root = std::env::temp_dir().join(format!("lwc-{}-cumulative", std::process::id()));
+        fs::create_dir_all(root.join("a/deep")).expect("created directories");
+        fs::create_dir_all(root.join("b")).expect("created directories");
+        fs::write(root.join("a/deep/f"), "x".repeat(10_000)).expect("written file");
+        fs::write(root.join("a/g"), "y").expect("written file");
+        fs::hard_link(root.join("a/deep/f"), root.join("b/f")).expect("hard link");
+
+        let options = Options::builder()
+            .which(Which::Dir)
+            .recursive(true)
+            .cumulative(true)
+            .build()
+            .expect("options");
+        let stats = count_many(&[&root], &options).expect("counted");
+
+        match stats.get(&root) {
+            Some(Ok(Stat::Dir(stat))) => assert_eq!(
+                (stat.tree_apparent_size, stat.tree_allocated_size),
+                tree_size(&root)
+            ),
+            stat => panic!("{stat:?}"),
+        }
+
+        fs::remove_dir_all(root).expect("removed directories");
+    }
+
+    #[test]
+    fn word_starts_match_split_whitespace() {
+        let spaces = (0..=0x3000)
+            .filter_map(char::from_u32)
+            .filter(|c| !c.is_ascii() && c.is_whitespace())
+            .collect::<String>();
+        let texts = [
+            "one  two\tthree\n\x0bfour".to_owned(),
+            format!("a{spaces}b c"),
+            spaces.chars().map(|c| format!("x{c}")).collect(),
+            "\u{200b}\u{a1}é 日本".to_owned(),
+            "word ".repeat(300),
+        ];
+
+        for text in texts {
+            let bytes = text.as_bytes();
+            let expected = text.split_whitespace().count();
+
+            assert_eq!(count_word_starts(bytes, false), expected, "{text:?}");
+            assert_eq!(
+                count_word_starts(bytes, true),
+                expected - usize::from(!text.starts_with(char::is_whitespace)),
+                "{text:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn unicode_spaces() {
+        for c in (0x80..=0x3000).filter_map(char::from_u32) {
+            let expected = c.is_whitespace();
+
+            for text in [format!("{c}"), format!("ab{c}"), format!("{c}ab")] {
+                assert_eq!(has_unicode_space(text.as_bytes()), expected, "{c:?}");
+            }
+        }
+    }
+}
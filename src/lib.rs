@@ -0,0 +1,21 @@
+//! Counting engine of `lwc`: lines, words, characters and bytes of files, and
+//! entries of directories, counted in parallel.
+//!
+//! [`count_many`] counts a list of paths, recursively or not, on a pool of
+//! threads and returns the result of each path. [`file()`], [`dir()`] and
+//! [`stdin()`] count a single input. What is counted is set by the [`Options`]
+//! built with [`Options::builder`]. Result types implement
+//! [`serde::Serialize`].
+//!
+//! The counts of lines, words, characters and bytes are [`Metric`]s, of
+//! [`Count`]. Other metrics are counted along them by implementing the trait,
+//! like [`Pattern`] which counts the matches of a regular expression.
+
+mod counter;
+mod metric;
+
+pub use counter::{
+    BinaryMode, DirStat, Encoding, EntryType, FileStat, Filter, LineLengths, Mark, Options,
+    OptionsBuilder, Stat, Which, WordMode, count, count_many, dir, extension, file, stdin,
+};
+pub use metric::{Count, Merge, Metric, MetricCounter, MetricValue, Pattern, PatternMode};
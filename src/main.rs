@@ -1,5 +1,4 @@
 mod command;
-mod counter;
 
 use colored::Colorize;
 use std::process::ExitCode;
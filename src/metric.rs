@@ -0,0 +1,334 @@
+use std::fmt;
+use std::ops;
+
+use regex::bytes::Regex;
+use serde::Serialize;
+
+use crate::counter::{CountCounter, FileStat};
+
+/// Maximum length of a line held to be matched against a regular expression.
+/// Longer lines are matched in pieces of this size, missing the matches that
+/// span two pieces.
+pub(crate) const MAX_MATCH_LINE: usize = 64 * 1024;
+
+/// A custom metric counted along the built-in counts of each file, in the same
+/// pass over its content.
+///
+/// Metrics are added with [`OptionsBuilder::metric`](crate::OptionsBuilder::metric).
+/// Their values are stored in [`FileStat::metrics`](crate::FileStat::metrics),
+/// in the order the metrics were added.
+///
+/// The counts of lines, words, characters and bytes are metrics too, of
+/// [`Count`]. The optional statistics aren't: they are computed by the scanner
+/// itself, sharing its passes over each block, and stored in the fields of
+/// [`FileStat`].
+pub trait Metric: fmt::Debug + Send + Sync {
+    /// Name of the metric, used as its column header.
+    fn name(&self) -> &str;
+
+    /// Returns a new counter of the metric for one input.
+    fn counter(&self) -> Box<dyn MetricCounter>;
+
+    /// Whether the metric can be counted over parts of an input split between
+    /// any two characters, the values of the parts being merged. Large files
+    /// are only split into parts counted in parallel if every metric can be.
+    /// `false` by default, as for metrics of lines or words.
+    fn chunked(&self) -> bool {
+        false
+    }
+
+    /// The built-in count the metric is, if any, whose value is then taken
+    /// from the counts of the scanner rather than from its counter. `None` by
+    /// default.
+    fn count(&self) -> Option<Count> {
+        None
+    }
+}
+
+/// Counting state of a [`Metric`] over one input.
+pub trait MetricCounter: fmt::Debug + Send {
+    /// Consumes the next chunk of the input. Chunks are UTF-8 text, converted
+    /// from the encoding of the input, and never split a character, but may
+    /// split a line or a word.
+    fn feed(&mut self, chunk: &[u8]);
+
+    /// Returns the value of the metric, once the whole input was fed.
+    fn finish(&mut self) -> MetricValue;
+}
+
+/// Value of a [`Metric`] for an input, merged with the values of other inputs
+/// into the totals as set by its [`Merge`]. Serialized as a number.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(transparent)]
+pub struct MetricValue {
+    value: usize,
+    #[serde(skip)]
+    merge: Merge,
+}
+
+/// How the values of a [`Metric`] for several inputs are merged.
+#[derive(Debug, Default, Clone, Copy)]
+#[non_exhaustive]
+pub enum Merge {
+    /// Summing them, as for a count.
+    #[default]
+    Sum,
+    /// Keeping the largest.
+    Max,
+    /// Keeping the smallest.
+    Min,
+    /// With a function of two values. Inputs are merged in any order, so it
+    /// should be associative and commutative.
+    With(fn(usize, usize) -> usize),
+}
+
+impl Merge {
+    fn apply(self, a: usize, b: usize) -> usize {
+        match self {
+            Self::Sum => a + b,
+            Self::Max => a.max(b),
+            Self::Min => a.min(b),
+            Self::With(f) => f(a, b),
+        }
+    }
+}
+
+impl MetricValue {
+    pub fn new(value: usize, merge: Merge) -> Self {
+        Self { value, merge }
+    }
+
+    /// A count, merged by summing.
+    pub fn sum(value: usize) -> Self {
+        Self::new(value, Merge::Sum)
+    }
+
+    /// A maximum, merged by keeping the largest.
+    pub fn max(value: usize) -> Self {
+        Self::new(value, Merge::Max)
+    }
+
+    pub fn get(self) -> usize {
+        self.value
+    }
+
+    pub fn merge(self) -> Merge {
+        self.merge
+    }
+}
+
+impl ops::AddAssign for MetricValue {
+    fn add_assign(&mut self, rhs: Self) {
+        self.value = self.merge.apply(self.value, rhs.value);
+    }
+}
+
+impl fmt::Display for MetricValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.get())
+    }
+}
+
+/// Built-in counts of [`FileStat`], as metrics.
+///
+/// The scanner computes them in its own vectorized passes over each block,
+/// words as set by [`OptionsBuilder::word_mode`](crate::OptionsBuilder::word_mode)
+/// and bytes before decoding, and doesn't feed their counters. Fed on their
+/// own, their counters tell words apart by whitespace, like the default mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Count {
+    Lines,
+    Words,
+    Chars,
+    Bytes,
+}
+
+impl Count {
+    /// Every count, in display order.
+    pub const ALL: &[Count] = &[Self::Lines, Self::Words, Self::Chars, Self::Bytes];
+
+    /// Returns the count of `stat`.
+    pub fn get(self, stat: &FileStat) -> usize {
+        match self {
+            Self::Lines => stat.lines,
+            Self::Words => stat.words,
+            Self::Chars => stat.chars,
+            Self::Bytes => stat.bytes,
+        }
+    }
+}
+
+impl Metric for Count {
+    fn name(&self) -> &str {
+        match self {
+            Self::Lines => "lines",
+            Self::Words => "words",
+            Self::Chars => "chars",
+            Self::Bytes => "bytes",
+        }
+    }
+
+    fn counter(&self) -> Box<dyn MetricCounter> {
+        Box::new(CountCounter::new(*self))
+    }
+
+    fn count(&self) -> Option<Count> {
+        Some(*self)
+    }
+}
+
+/// Counts the lines matching a regular expression, or its non-empty matches,
+/// in each file. Matches don't span lines, nor pieces of lines longer than
+/// 64 KiB.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    regex: Regex,
+    mode: PatternMode,
+    /// The regular expression followed by what is counted, like `TODO (lines)`.
+    name: String,
+}
+
+/// What a [`Pattern`] counts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PatternMode {
+    /// Lines with at least one match, like `grep -c`.
+    #[default]
+    Lines,
+    /// All the matches, several per line.
+    Matches,
+}
+
+impl Pattern {
+    pub fn new(regex: Regex, mode: PatternMode) -> Self {
+        let counted = match mode {
+            PatternMode::Lines => "lines",
+            PatternMode::Matches => "matches",
+        };
+        let name = format!("{} ({counted})", regex.as_str());
+
+        Self { regex, mode, name }
+    }
+}
+
+impl Metric for Pattern {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn counter(&self) -> Box<dyn MetricCounter> {
+        Box::new(LineMatcher::new(self.regex.clone(), self.mode))
+    }
+}
+
+/// Regular expression of a [`LineMatcher`], over `str` or bytes.
+pub(crate) trait Matcher: fmt::Debug + Send {
+    fn is_match_in(&self, text: &[u8]) -> bool;
+
+    /// Counts the non-empty matches in `text`.
+    fn count_in(&self, text: &[u8]) -> usize;
+}
+
+impl Matcher for Regex {
+    fn is_match_in(&self, text: &[u8]) -> bool {
+        self.is_match(text)
+    }
+
+    fn count_in(&self, text: &[u8]) -> usize {
+        self.find_iter(text).filter(|m| !m.is_empty()).count()
+    }
+}
+
+// Lines are only split before a line feed or a carriage return, so the pieces
+// of UTF-8 text are UTF-8 too.
+impl Matcher for regex::Regex {
+    fn is_match_in(&self, text: &[u8]) -> bool {
+        std::str::from_utf8(text).is_ok_and(|text| self.is_match(text))
+    }
+
+    fn count_in(&self, text: &[u8]) -> usize {
+        std::str::from_utf8(text).map_or(0, |text| {
+            self.find_iter(text).filter(|m| !m.is_empty()).count()
+        })
+    }
+}
+
+/// Counts the lines of a stream of text matching a regular expression, or its
+/// matches, as set by a [`PatternMode`]. Line terminators aren't matched, and
+/// lines longer than [`MAX_MATCH_LINE`] are matched in pieces.
+#[derive(Debug)]
+pub(crate) struct LineMatcher<R> {
+    regex: R,
+    mode: PatternMode,
+    /// The part of the line being scanned not matched yet.
+    line: Vec<u8>,
+    /// Whether a line is being scanned, so that an input ending with a line
+    /// feed has no empty last line.
+    in_line: bool,
+    /// Whether the line being scanned matched already.
+    matched: bool,
+    count: usize,
+}
+
+impl<R: Matcher> LineMatcher<R> {
+    pub(crate) fn new(regex: R, mode: PatternMode) -> Self {
+        Self {
+            regex,
+            mode,
+            line: vec![],
+            in_line: false,
+            matched: false,
+            count: 0,
+        }
+    }
+
+    /// Matches the first `len` bytes of the line being scanned, then drops
+    /// them.
+    fn match_piece(&mut self, len: usize) {
+        let piece = &self.line[..len];
+
+        match self.mode {
+            PatternMode::Lines => self.matched = self.matched || self.regex.is_match_in(piece),
+            PatternMode::Matches => self.count += self.regex.count_in(piece),
+        }
+
+        self.line.drain(..len);
+    }
+
+    fn end_line(&mut self) {
+        let len = self.line.len() - usize::from(self.line.ends_with(b"\r"));
+        self.match_piece(len);
+        self.line.clear();
+
+        self.count += usize::from(std::mem::take(&mut self.matched));
+        self.in_line = false;
+    }
+}
+
+impl<R: Matcher> MetricCounter for LineMatcher<R> {
+    fn feed(&mut self, chunk: &[u8]) {
+        for (i, segment) in chunk.split(|&b| b == b'\n').enumerate() {
+            if i > 0 {
+                self.end_line();
+            }
+
+            self.line.extend_from_slice(segment);
+            self.in_line |= !segment.is_empty();
+
+            if self.line.len() > MAX_MATCH_LINE {
+                // A carriage return is kept until the line feed after it.
+                let len = self.line.len() - usize::from(self.line.ends_with(b"\r"));
+                self.match_piece(len);
+            }
+        }
+    }
+
+    fn finish(&mut self) -> MetricValue {
+        if self.in_line {
+            self.end_line();
+        }
+
+        MetricValue::sum(std::mem::take(&mut self.count))
+    }
+}